@@ -1,10 +1,11 @@
 use kube::CustomResourceExt;
 
-use multi_deployment_controller::crd::MultiDeployment;
+use multi_deployment_controller::crd::{MultiDeployment, PlacementProfile};
 
 fn main() {
     print!(
-        "{}",
-        serde_yaml::to_string(&MultiDeployment::crd()).unwrap()
+        "{}---\n{}",
+        serde_yaml::to_string(&MultiDeployment::crd()).unwrap(),
+        serde_yaml::to_string(&PlacementProfile::crd()).unwrap()
     )
 }
@@ -5,7 +5,9 @@ use k8s_openapi::{
         apps::v1::DeploymentSpec,
         core::v1::{Container, PodSpec, PodTemplateSpec, ResourceRequirements},
     },
-    apimachinery::pkg::{api::resource::Quantity, apis::meta::v1::LabelSelector},
+    apimachinery::pkg::{
+        api::resource::Quantity, apis::meta::v1::LabelSelector, util::intstr::IntOrString,
+    },
 };
 use kube::api::ObjectMeta;
 use multi_deployment_controller::crd::{ChildDeployment, MultiDeployment, MultiDeploymentSpec};
@@ -38,7 +40,6 @@ fn main() {
                     spec: Some(PodSpec {
                         ..Default::default()
                     }),
-                    ..Default::default()
                 },
                 ..Default::default()
             },
@@ -47,8 +48,8 @@ fn main() {
                     "child-a".to_string(),
                     ChildDeployment {
                         weight: Some(70),
-                        min_replicas: Some(1),
-                        pod_spec: PodSpec {
+                        min_replicas: Some(IntOrString::Int(1)),
+                        pod_spec: Some(PodSpec {
                             containers: vec![Container {
                                 name: "debian".to_string(),
                                 image: Some("debian:latest".to_string()),
@@ -63,15 +64,16 @@ fn main() {
                                 ..Default::default()
                             }],
                             ..Default::default()
-                        },
+                        }),
+                        ..Default::default()
                     },
                 ),
                 (
                     "child-b".to_string(),
                     ChildDeployment {
                         weight: Some(30),
-                        min_replicas: Some(1),
-                        pod_spec: PodSpec {
+                        min_replicas: Some(IntOrString::Int(1)),
+                        pod_spec: Some(PodSpec {
                             containers: vec![Container {
                                 name: "ubuntu".to_string(),
                                 image: Some("ubuntu:latest".to_string()),
@@ -86,10 +88,12 @@ fn main() {
                                 ..Default::default()
                             }],
                             ..Default::default()
-                        },
+                        }),
+                        ..Default::default()
                     },
                 ),
             ]),
+            ..Default::default()
         },
 
         status: None,
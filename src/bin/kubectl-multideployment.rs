@@ -0,0 +1,287 @@
+use std::process::ExitCode;
+
+use anyhow::{Context, bail};
+use k8s_openapi::{
+    api::apps::v1::Deployment,
+    chrono::{SecondsFormat, Utc},
+};
+use kube::{
+    Api, Client, ResourceExt,
+    api::{Patch, PatchParams},
+};
+use serde_json::{Value, json};
+
+use multi_deployment_controller::{crd::MultiDeployment, render};
+
+const FIELD_MANAGER: &str = "kubectl-multideployment";
+
+const USAGE: &str = "\
+Operates MultiDeployments.
+
+Usage: kubectl multideployment [-n NAMESPACE] COMMAND
+
+Commands:
+  status NAME                     Replicas of each child and of its Deployment
+  set-weight NAME CHILD WEIGHT    Set the weight of a child
+  scale NAME --replicas N         Set the total replicas
+  pause NAME                      Stop reconciling the child Deployments
+  resume NAME                     Reconcile the child Deployments again
+  promote NAME CHILD              Move all weighted replicas to a child, e.g. a canary
+  restart NAME [CHILD]            Restart the pods of a child, or of all children
+
+Options:
+  -n, --namespace NS    Namespace of the MultiDeployment, defaults to the current context";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
+    let mut namespace = None;
+    let mut replicas = None;
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-n" | "--namespace" => namespace = Some(args.next().context("-n needs a value")?),
+            "--replicas" => {
+                let value = args.next().context("--replicas needs a value")?;
+                replicas = Some(
+                    value
+                        .parse::<i32>()
+                        .with_context(|| format!("invalid --replicas {}", value))?,
+                );
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => bail!("unknown argument {}\n\n{}", arg, USAGE),
+            _ => positional.push(arg),
+        }
+    }
+
+    let client = Client::try_default().await?;
+    let namespace = namespace.unwrap_or_else(|| client.default_namespace().to_string());
+    let multi_deployments: Api<MultiDeployment> = Api::namespaced(client.clone(), &namespace);
+    let deployments: Api<Deployment> = Api::namespaced(client, &namespace);
+
+    let positional: Vec<&str> = positional.iter().map(String::as_str).collect();
+    match (positional.as_slice(), replicas) {
+        (["status", name], None) => status(&multi_deployments, &deployments, name).await,
+        (["set-weight", name, child_name, weight], None) => {
+            let weight: i32 = weight
+                .parse()
+                .with_context(|| format!("invalid weight {}", weight))?;
+            let md = multi_deployments.get(name).await?;
+            check_child(&md, child_name)?;
+            let patch = json!({"spec": {"children": {*child_name: {"weight": weight}}}});
+            patch_spec(&multi_deployments, name, patch).await?;
+            println!("{} child {} weight set to {}", name, child_name, weight);
+            Ok(())
+        }
+        (["scale", name], Some(replicas)) => {
+            patch_spec(
+                &multi_deployments,
+                name,
+                json!({"spec": {"replicas": replicas}}),
+            )
+            .await?;
+            println!("{} scaled to {} replicas", name, replicas);
+            Ok(())
+        }
+        (["pause", name], None) => {
+            patch_spec(&multi_deployments, name, json!({"spec": {"paused": true}})).await?;
+            println!("{} paused", name);
+            Ok(())
+        }
+        (["resume", name], None) => {
+            patch_spec(&multi_deployments, name, json!({"spec": {"paused": null}})).await?;
+            println!("{} resumed", name);
+            Ok(())
+        }
+        (["promote", name, child_name], None) => {
+            let md = multi_deployments.get(name).await?;
+            check_child(&md, child_name)?;
+            if md.spec.children[*child_name].replicas.is_some() {
+                bail!("child {} has pinned replicas", child_name);
+            }
+            // pinned children keep their replicas, all others go to the child
+            let children: serde_json::Map<String, Value> = md
+                .spec
+                .children
+                .iter()
+                .filter(|(_, child)| child.replicas.is_none())
+                .map(|(other, _)| {
+                    let weight = if other == child_name { 100 } else { 0 };
+                    (other.clone(), json!({"weight": weight}))
+                })
+                .collect();
+            patch_spec(
+                &multi_deployments,
+                name,
+                json!({"spec": {"children": children}}),
+            )
+            .await?;
+            println!("{} child {} promoted", name, child_name);
+            Ok(())
+        }
+        (["restart", name, rest @ ..], None) if rest.len() <= 1 => {
+            let restarted_at = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+            let patch = match rest.first() {
+                Some(child_name) => {
+                    let md = multi_deployments.get(name).await?;
+                    check_child(&md, child_name)?;
+                    json!({"spec": {"children": {*child_name: {"restartedAt": restarted_at}}}})
+                }
+                None => json!({"spec": {"restartedAt": restarted_at}}),
+            };
+            patch_spec(&multi_deployments, name, patch).await?;
+            match rest.first() {
+                Some(child_name) => println!("{} child {} restarted", name, child_name),
+                None => println!("{} restarted", name),
+            }
+            Ok(())
+        }
+        _ => bail!("{}", USAGE),
+    }
+}
+
+/// Children can only be changed when they are part of `spec.children`.
+fn check_child(md: &MultiDeployment, child_name: &str) -> anyhow::Result<()> {
+    if md.spec.children.contains_key(child_name) {
+        return Ok(());
+    }
+    let generated = md
+        .status
+        .as_ref()
+        .and_then(|s| s.generated_children.as_ref())
+        .is_some_and(|g| g.iter().any(|name| name == child_name));
+    if generated {
+        bail!(
+            "child {} is generated, change its generator instead",
+            child_name
+        );
+    }
+    bail!("{} has no child {}", md.name_any(), child_name)
+}
+
+async fn patch_spec(api: &Api<MultiDeployment>, name: &str, patch: Value) -> anyhow::Result<()> {
+    let params = PatchParams {
+        field_manager: Some(FIELD_MANAGER.to_string()),
+        ..Default::default()
+    };
+    api.patch(name, &params, &Patch::Merge(patch))
+        .await
+        .with_context(|| format!("patching MultiDeployment {}", name))?;
+    Ok(())
+}
+
+async fn status(
+    multi_deployments: &Api<MultiDeployment>,
+    deployments: &Api<Deployment>,
+    name: &str,
+) -> anyhow::Result<()> {
+    let md = multi_deployments.get(name).await?;
+    let (expanded, _) = render::expand(&md)?;
+    let children_status = md
+        .status
+        .as_ref()
+        .and_then(|s| s.children.clone())
+        .unwrap_or_default();
+
+    let mut rows = vec![
+        [
+            "CHILD",
+            "WEIGHT",
+            "EFFECTIVE",
+            "DESIRED",
+            "READY",
+            "AVAILABLE",
+        ]
+        .map(String::from),
+    ];
+    for (child_name, child) in &expanded.spec.children {
+        let weight = match (child.replicas, child.weight) {
+            (Some(_), _) => "pinned".to_string(),
+            (None, weight) => weight.unwrap_or(0).to_string(),
+        };
+        let child_status = children_status.get(child_name);
+        let effective = child_status.map_or("-".to_string(), |c| c.effective_replicas.to_string());
+        let deployment = deployments
+            .get_opt(&render::deployment_name(name, child_name))
+            .await?;
+        let (desired, ready, available) = match deployment {
+            Some(d) => {
+                let status = d.status.unwrap_or_default();
+                (
+                    d.spec.and_then(|s| s.replicas).unwrap_or(1).to_string(),
+                    status.ready_replicas.unwrap_or(0).to_string(),
+                    status.available_replicas.unwrap_or(0).to_string(),
+                )
+            }
+            None => ("-".to_string(), "-".to_string(), "-".to_string()),
+        };
+        let mut child_name = child_name.clone();
+        if child_status.is_some_and(|c| c.patch_error.is_some()) {
+            child_name.push_str(" (patch failed)");
+        }
+        if let Some(waiting_for) = child_status.and_then(|c| c.waiting_for.as_ref()) {
+            child_name.push_str(&format!(" (waiting for {})", waiting_for.join(", ")));
+        }
+        if let Some(expires_at) = child_status.and_then(|c| c.expires_at.as_ref()) {
+            let expired = child_status.and_then(|c| c.expired) == Some(true);
+            let state = if expired { "expired" } else { "expires" };
+            child_name.push_str(&format!(
+                " ({} {})",
+                state,
+                expires_at.0.to_rfc3339_opts(SecondsFormat::Secs, true)
+            ));
+        }
+        rows.push([child_name, weight, effective, desired, ready, available]);
+    }
+
+    let paused = if md.spec.paused == Some(true) {
+        " (paused)"
+    } else {
+        ""
+    };
+    println!(
+        "MultiDeployment {}: {} replicas{}",
+        name,
+        md.spec.replicas.unwrap_or(0),
+        paused
+    );
+    if let Some(status) = &md.status {
+        if let Some(child_name) = &status.rollout_child {
+            println!("Rolling out child {}", child_name);
+        }
+        for condition in status.conditions.iter().flatten() {
+            println!("{}: {}", condition.type_, condition.message);
+        }
+    }
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    for row in &rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+    for (child_name, child_status) in &children_status {
+        if let Some(message) = &child_status.patch_error {
+            eprintln!("child {}: {}", child_name, message);
+        }
+    }
+    Ok(())
+}
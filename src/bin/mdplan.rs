@@ -0,0 +1,389 @@
+use std::{io::Read, process::ExitCode};
+
+use anyhow::{Context, bail};
+use k8s_openapi::{api::apps::v1::Deployment, chrono::Utc};
+use kube::{Api, Client, ResourceExt};
+use serde::Deserialize;
+
+use multi_deployment_controller::{
+    crd::{MultiDeployment, PlacementProfile},
+    diff::{self, Change},
+    render::{self, RenderOptions, Rendered},
+};
+
+const USAGE: &str = "\
+Renders MultiDeployments into their child Deployments, and compares them with
+the live ones.
+
+Usage: mdplan render [-f FILE]... [--replicas N] [-o yaml|table]
+       mdplan diff [-f FILE]... [--replicas N] [-n NAMESPACE] [--live FILE]...
+
+Options:
+  -f, --filename FILE    MultiDeployment YAML to render, '-' for stdin (default).
+                         PlacementProfile documents are used by children referring to them.
+  --replicas N           Render with N total replicas instead of spec.replicas
+  -o, --output FORMAT    yaml (default): the rendered Deployments
+                         table: the allocation of each child
+  -n, --namespace NS     diff: namespace of the live objects, defaults to the
+                         namespace of the MultiDeployment or the current context
+  --live FILE            diff: compare with exported YAML (Deployments and the live
+                         MultiDeployment) instead of the cluster
+
+diff exits with 1 if there are differences.";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Output {
+    Yaml,
+    Table,
+}
+
+struct Args {
+    files: Vec<String>,
+    replicas: Option<i32>,
+    output: Output,
+    namespace: Option<String>,
+    live: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+async fn run() -> anyhow::Result<ExitCode> {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("render") => render_command(parse_args(args)?),
+        Some("diff") => diff_command(parse_args(args)?).await,
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => bail!("{}", USAGE),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Args> {
+    let mut parsed = Args {
+        files: Vec::new(),
+        replicas: None,
+        output: Output::Yaml,
+        namespace: None,
+        live: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "-f" | "--filename" => parsed.files.push(value()?),
+            "--replicas" => {
+                let replicas = value()?;
+                parsed.replicas = Some(
+                    replicas
+                        .parse()
+                        .with_context(|| format!("invalid --replicas {}", replicas))?,
+                );
+            }
+            "-o" | "--output" => {
+                parsed.output = match value()?.as_str() {
+                    "yaml" => Output::Yaml,
+                    "table" => Output::Table,
+                    other => bail!("unknown output format {}", other),
+                }
+            }
+            "-n" | "--namespace" => parsed.namespace = Some(value()?),
+            "--live" => parsed.live.push(value()?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            other => bail!("unknown argument {}\n\n{}", other, USAGE),
+        }
+    }
+    if parsed.files.is_empty() {
+        parsed.files.push("-".to_string());
+    }
+    Ok(parsed)
+}
+
+fn render_command(args: Args) -> anyhow::Result<ExitCode> {
+    let manifests = read_manifests(&args.files)?;
+    let (multi_deployments, mut options) = (manifests.multi_deployments, manifests.options);
+    options.now = Some(Utc::now());
+    if multi_deployments.is_empty() {
+        bail!("no MultiDeployment found");
+    }
+
+    for (i, mut md) in multi_deployments.into_iter().enumerate() {
+        if args.replicas.is_some() {
+            md.spec.replicas = args.replicas;
+        }
+        let rendered = render::render(&md, &options)
+            .with_context(|| format!("MultiDeployment {}", md.name_any()))?;
+        for (child_name, message) in &rendered.patch_errors {
+            eprintln!(
+                "MultiDeployment {}: patches of child {} failed: {}",
+                md.name_any(),
+                child_name,
+                message
+            );
+        }
+        match args.output {
+            Output::Yaml => {
+                for (j, deployment) in rendered.deployments.iter().enumerate() {
+                    if i > 0 || j > 0 {
+                        println!("---");
+                    }
+                    print!("{}", serde_yaml::to_string(deployment)?);
+                }
+            }
+            Output::Table => {
+                if i > 0 {
+                    println!();
+                }
+                // generated children, as rendered
+                let (expanded, _) = render::expand(&md)?;
+                print_table(&expanded, &rendered);
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn diff_command(args: Args) -> anyhow::Result<ExitCode> {
+    let manifests = read_manifests(&args.files)?;
+    if manifests.multi_deployments.is_empty() {
+        bail!("no MultiDeployment found");
+    }
+    let live = if args.live.is_empty() {
+        None
+    } else {
+        Some(read_manifests(&args.live)?)
+    };
+    let client = match live {
+        Some(_) => None,
+        None => Some(Client::try_default().await?),
+    };
+
+    let mut changed = false;
+    for mut md in manifests.multi_deployments {
+        if args.replicas.is_some() {
+            md.spec.replicas = args.replicas;
+        }
+        let mut options = manifests.options.clone();
+        options.now = Some(Utc::now());
+
+        // live state of the MultiDeployment and its Deployments
+        let (live_md, live_deployments) = match (&live, &client) {
+            (Some(live), _) => (
+                live.multi_deployments
+                    .iter()
+                    .find(|m| m.name_any() == md.name_any())
+                    .cloned(),
+                live.deployments.clone(),
+            ),
+            (None, Some(client)) => {
+                let namespace = args
+                    .namespace
+                    .clone()
+                    .or_else(|| md.namespace())
+                    .unwrap_or_else(|| client.default_namespace().to_string());
+                let mds: Api<MultiDeployment> = Api::namespaced(client.clone(), &namespace);
+                let deployments: Api<Deployment> = Api::namespaced(client.clone(), &namespace);
+                let profiles: Api<PlacementProfile> = Api::all(client.clone());
+                let (expanded, _) = render::expand(&md)?;
+                for name in render::cluster_placement_profiles(&expanded) {
+                    if !options.placement_profiles.contains_key(&name)
+                        && let Some(profile) = profiles.get_opt(&name).await?
+                    {
+                        options.placement_profiles.insert(name, profile.spec);
+                    }
+                }
+                (
+                    mds.get_opt(&md.name_any()).await?,
+                    deployments.list(&Default::default()).await?.items,
+                )
+            }
+            (None, None) => unreachable!(),
+        };
+
+        // render like the controller would, on top of the live object
+        let (expanded, _) = render::expand(&md)?;
+        for child_name in expanded.spec.children.keys() {
+            let name = render::deployment_name(&md.name_any(), child_name);
+            if let Some(created) = live_deployments
+                .iter()
+                .find(|d| d.name_any() == name)
+                .and_then(|d| d.metadata.creation_timestamp.clone())
+            {
+                options
+                    .deployments_created
+                    .insert(child_name.clone(), created.0);
+            }
+        }
+        if let Some(live_md) = live_md {
+            if md.metadata.uid.is_none() {
+                md.metadata.uid = live_md.metadata.uid;
+            }
+            options.previous = live_md.status;
+        }
+        let rendered = render::render(&md, &options)
+            .with_context(|| format!("MultiDeployment {}", md.name_any()))?;
+        for (child_name, message) in &rendered.patch_errors {
+            eprintln!(
+                "MultiDeployment {}: patches of child {} failed: {}",
+                md.name_any(),
+                child_name,
+                message
+            );
+        }
+
+        let changes = diff::diff_deployments(&md, &rendered, &live_deployments)?;
+        changed |= !changes.is_empty();
+        print_changes(&changes);
+    }
+    Ok(if changed {
+        ExitCode::from(1)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn print_changes(changes: &[Change]) {
+    let show = |value: &Option<serde_json::Value>| {
+        value
+            .as_ref()
+            .map_or("<none>".to_string(), |v| v.to_string())
+    };
+    for change in changes {
+        match change {
+            Change::Create(name) => println!("+ Deployment {} will be created", name),
+            Change::Prune(name) => println!("- Deployment {} will be deleted", name),
+            Change::Modify(name, fields) => {
+                println!("~ Deployment {} will be modified", name);
+                for field in fields {
+                    println!(
+                        "    {}: {} -> {}",
+                        field.path,
+                        show(&field.live),
+                        show(&field.rendered)
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Manifests {
+    multi_deployments: Vec<MultiDeployment>,
+    deployments: Vec<Deployment>,
+    /// Placement profiles of the manifests
+    options: RenderOptions,
+}
+
+/// Objects of all documents in `files`, including the items of lists.
+fn read_manifests(files: &[String]) -> anyhow::Result<Manifests> {
+    let mut manifests = Manifests::default();
+    for file in files {
+        let content = if file == "-" {
+            let mut content = String::new();
+            std::io::stdin().read_to_string(&mut content)?;
+            content
+        } else {
+            std::fs::read_to_string(file).with_context(|| format!("reading {}", file))?
+        };
+        for document in serde_yaml::Deserializer::from_str(&content) {
+            let value = serde_yaml::Value::deserialize(document)
+                .with_context(|| format!("parsing {}", file))?;
+            add_manifest(&mut manifests, value).with_context(|| format!("in {}", file))?;
+        }
+    }
+    Ok(manifests)
+}
+
+fn add_manifest(manifests: &mut Manifests, value: serde_yaml::Value) -> anyhow::Result<()> {
+    match value.get("kind").and_then(|k| k.as_str()) {
+        Some("MultiDeployment") => manifests
+            .multi_deployments
+            .push(serde_yaml::from_value(value).context("invalid MultiDeployment")?),
+        Some("Deployment") => manifests
+            .deployments
+            .push(serde_yaml::from_value(value).context("invalid Deployment")?),
+        Some("PlacementProfile") => {
+            let profile: PlacementProfile =
+                serde_yaml::from_value(value).context("invalid PlacementProfile")?;
+            manifests
+                .options
+                .placement_profiles
+                .insert(profile.name_any(), profile.spec);
+        }
+        // e.g. kubectl get -o yaml
+        Some("List") => {
+            let items = value.get("items").and_then(|i| i.as_sequence()).cloned();
+            for item in items.unwrap_or_default() {
+                add_manifest(manifests, item)?;
+            }
+        }
+        // other documents of a manifest, and empty ones
+        _ => {}
+    }
+    Ok(())
+}
+
+fn print_table(md: &MultiDeployment, rendered: &Rendered) {
+    let allocation = &rendered.allocation;
+    let mut rows = vec![[
+        "CHILD".to_string(),
+        "WEIGHT".to_string(),
+        "MIN".to_string(),
+        "MAX".to_string(),
+        "REPLICAS".to_string(),
+    ]];
+    for (i, child_name) in allocation.children.iter().enumerate() {
+        let child = &md.spec.children[child_name];
+        let weight = match (child.replicas, child.weight) {
+            (Some(_), _) => "pinned".to_string(),
+            (None, weight) => weight.unwrap_or(0).to_string(),
+        };
+        let mut name = child_name.clone();
+        if rendered.patch_errors.contains_key(child_name) {
+            name.push_str(" (patch failed)");
+        }
+        if rendered.expired_children.contains(child_name) {
+            name.push_str(" (expired)");
+        }
+        rows.push([
+            name,
+            weight,
+            allocation.minimums[i].to_string(),
+            allocation.maximums[i].map_or("-".to_string(), |m| m.to_string()),
+            allocation.replicas[i].to_string(),
+        ]);
+    }
+
+    println!(
+        "MultiDeployment {}: {} replicas",
+        md.name_any(),
+        md.spec.replicas.unwrap_or(0)
+    );
+    let widths: Vec<usize> = (0..5)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    for row in &rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
@@ -1,16 +1,35 @@
-use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use k8s_openapi::api::{
-    apps::v1::{Deployment, DeploymentSpec},
-    core::v1::PodTemplateSpec,
+use k8s_openapi::{
+    api::{
+        apps::v1::Deployment,
+        core::v1::{Node, Pod},
+    },
+    apimachinery::pkg::apis::meta::v1::{Condition, Time},
+    chrono::{DateTime, TimeDelta, Utc},
 };
 use kube::{
-    Resource, ResourceExt, api::{ObjectMeta, Patch, PatchParams}, core::Selector, runtime::controller::Action
+    Resource, ResourceExt,
+    api::{ListParams, Patch, PatchParams},
+    core::Selector,
+    runtime::{
+        controller::Action,
+        events::{Event, EventType},
+        reflector::ObjectRef,
+        reflector::Store,
+    },
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
+pub use crate::render::LABEL_SELECTOR_KEY;
 use crate::{
-    crd::MultiDeployment,
+    crd::{ChildStatus, MultiDeployment, MultiDeploymentStatus, PlacementProfile},
+    diff,
+    render::{self, CONTROLLER_NAME, RenderOptions},
     types::{Context, Error},
     utils,
 };
@@ -19,103 +38,438 @@ const RESOURCE_GROUP: &str = "skystar.dev";
 const RESOURCE_VERSION: &str = "v1";
 const RESOURCE_KIND: &str = "MultiDeployment";
 
-const CONTROLLER_NAME: &str = "multi-deployment-controller";
-const LABEL_SELECTOR_KEY: &str = "multi-deployment.skystar.dev/managed-by";
+const REALLOCATION_REQUEUE_INTERVAL: TimeDelta = TimeDelta::seconds(15);
+const DEFAULT_REBALANCE_INTERVAL_SECONDS: i64 = 60;
+const DEFAULT_UNSCHEDULABLE_SECONDS: i64 = 120;
+const DEFAULT_FALLBACK_RETRY_SECONDS: i64 = 600;
 
 pub async fn reconcile(obj: Arc<MultiDeployment>, ctx: Arc<Context>) -> Result<Action, Error> {
     info!("Reconciling MultiDeployment: {}", obj.name_any());
+    if obj.spec.paused == Some(true) {
+        info!("MultiDeployment {} is paused", obj.name_any());
+        return Ok(Action::await_change());
+    }
     let multi_deployments = ctx.multi_deployments.clone();
     let deployments = ctx.deployments.clone();
 
-    // check that at least one child deployment is defined
-    if obj.spec.children.len() == 0 {
-        return Err(Error::ValidationError(
-            "At least one child deployment must be defined".to_string(),
-        ));
-    }
+    // add generated children, they are handled like the others from here on
+    let (mut obj, generated_children) = render::expand(&obj)?;
 
-    // validate that no child deployment has negative values
-    if obj
-        .spec
-        .children
-        .values()
-        .map(|c| c.weight.unwrap_or(0))
-        .any(|w| w < 0)
-    {
-        return Err(Error::ValidationError(
-            "Child deployment weights cannot be negative".to_string(),
-        ));
+    // fetch cluster-wide placement profiles children refer to
+    let mut placement_profiles = BTreeMap::new();
+    for name in render::cluster_placement_profiles(&obj) {
+        match ctx.placement_profiles.get_opt(&name).await? {
+            Some(profile) => {
+                placement_profiles.insert(name, profile.spec);
+            }
+            None => {
+                return Err(Error::ValidationError(format!(
+                    "Placement profile {} not found",
+                    name
+                )));
+            }
+        }
     }
-    if obj
-        .spec
-        .children
-        .values()
-        .map(|c| c.min_replicas.unwrap_or(0))
-        .any(|r| r < 0)
-    {
-        return Err(Error::ValidationError(
-            "Child deployment min_replicas cannot be negative".to_string(),
-        ));
+    // expired children are scaled down like children without weight, and
+    // their Deployments are deleted once they have no replicas left. A ttl
+    // counts from the creation of the child's Deployment.
+    let now = Utc::now();
+    let mut requeue = None;
+    let source_name = obj.name_any();
+    let mut deployments_created = BTreeMap::new();
+    for (child_name, child) in &obj.spec.children {
+        if child.ttl.is_some()
+            && let Some(created) = deployments
+                .get_opt(&render::deployment_name(&source_name, child_name))
+                .await?
+                .and_then(|d| d.metadata.creation_timestamp)
+        {
+            deployments_created.insert(child_name.clone(), created.0);
+        }
+    }
+    let options = RenderOptions {
+        previous: obj.status.clone(),
+        placement_profiles,
+        now: Some(now),
+        deployments_created,
+    };
+    let expiries = render::expire(&mut obj, &options);
+    let previous_children_status = obj
+        .status
+        .as_ref()
+        .and_then(|s| s.children.clone())
+        .unwrap_or_default();
+    for (child_name, expiry) in &expiries {
+        if !expiry.expired {
+            requeue = earliest(requeue, expiry.expires_at - now);
+        } else if previous_children_status
+            .get(child_name)
+            .and_then(|c| c.expired)
+            != Some(true)
+        {
+            info!("Child deployment {} expired", child_name);
+            publish_event(
+                &ctx,
+                &obj,
+                "ChildExpired",
+                format!(
+                    "Child {} expired, its Deployment is deleted once it is scaled down",
+                    child_name
+                ),
+            )
+            .await;
+        }
     }
 
+    let allocation = render::allocate(&obj, &options)?;
     let total_replicas = obj.spec.replicas.unwrap_or(0);
-    if total_replicas < 0 {
+    let allocation_hash = allocation.inputs_hash.clone();
+    let maximums = allocation.maximums;
+    let calculated_replicas = allocation.replicas;
+    let previous_status = obj.status.clone().unwrap_or_default();
+    let previous_children = previous_status.children.unwrap_or_default();
+
+    let max_unavailable = match &obj.spec.max_unavailable {
+        Some(value) => utils::resolve_int_or_percent(value, total_replicas.into(), false)?,
+        None => 0,
+    };
+    if max_unavailable < 0 {
         return Err(Error::ValidationError(
-            "Total replicas cannot be negative".to_string(),
+            "maxUnavailable cannot be negative".to_string(),
         ));
     }
 
-    // calculate replicas
-    let total_min_replicas: i32 = obj
-        .spec
-        .children
-        .values()
-        .map(|child| child.min_replicas.unwrap_or(0))
-        .sum();
-
-    // validate that total min_replicas does not exceed total replicas
-    // total_replicas == 0 is exception, meaning "(temporarily) disabled"
-    if total_min_replicas > total_replicas && total_replicas != 0 {
-        return Err(Error::ValidationError(
-            "Sum of min_replicas of child deployments exceeds total replicas".to_string(),
-        ));
+    // look up current state of child deployments
+    let mut current_replicas = Vec::with_capacity(obj.spec.children.len());
+    let mut available_replicas = Vec::with_capacity(obj.spec.children.len());
+    let mut live_deployments = Vec::with_capacity(obj.spec.children.len());
+    for child_name in obj.spec.children.keys() {
+        let existing = deployments
+            .get_opt(&format!("{}-{}", source_name, child_name))
+            .await?;
+        let (current, available) = match &existing {
+            Some(d) => (
+                d.spec.as_ref().and_then(|s| s.replicas).unwrap_or(1),
+                d.status
+                    .as_ref()
+                    .and_then(|s| s.available_replicas)
+                    .unwrap_or(0),
+            ),
+            None => (0, 0),
+        };
+        current_replicas.push(current.into());
+        available_replicas.push(available.into());
+        live_deployments.push(existing);
     }
 
-    let total_weight: i32 = obj
-        .spec
-        .children
-        .values()
-        .map(|child| child.weight.unwrap_or(0))
-        .sum();
+    // move towards the calculated allocation, limited by the rebalance policy
+    let mut last_rebalance_time = previous_status.last_rebalance_time.clone();
+    let allocated_replicas = match &obj.spec.rebalance {
+        None => calculated_replicas.clone(),
+        Some(policy) => {
+            // children without recorded allocation start from their current replicas
+            let previous_replicas: Vec<i64> = obj
+                .spec
+                .children
+                .keys()
+                .enumerate()
+                .map(|(i, child_name)| {
+                    previous_children
+                        .get(child_name)
+                        .map(|c| c.replicas.into())
+                        .unwrap_or(current_replicas[i])
+                })
+                .collect();
 
-    if total_weight == 0 && total_replicas != 0 {
-        // total_replicas is non-zero, but total_weight is zero
-        // this can be regarded as even distribution, but to avoid confusion, we raise an error
-        return Err(Error::ValidationError(
-            "Total weight of child deployments cannot be zero when total replicas is non-zero"
-                .to_string(),
+            let interval = TimeDelta::seconds(
+                policy
+                    .interval_seconds
+                    .unwrap_or(DEFAULT_REBALANCE_INTERVAL_SECONDS),
+            );
+            let elapsed = last_rebalance_time.as_ref().map(|t| now - t.0);
+            let due = elapsed.is_none_or(|e| e >= interval);
+            let max_moved = if due {
+                utils::resolve_int_or_percent(&policy.max_replicas, total_replicas.into(), true)?
+                    .max(1)
+            } else {
+                0
+            };
+
+            let allocated =
+                utils::rebalance_step(&previous_replicas, &calculated_replicas, max_moved)?;
+            if due && allocated != previous_replicas {
+                last_rebalance_time = Some(Time(now));
+            }
+            if allocated != calculated_replicas {
+                let wait = match elapsed {
+                    Some(e) if !due => interval - e,
+                    _ => interval,
+                };
+                requeue = earliest(requeue, wait);
+            }
+            allocated
+        }
+    };
+
+    // move replicas whose pods cannot be scheduled to fallback children
+    let child_names: Vec<&String> = obj.spec.children.keys().collect();
+    let mut effective_replicas = allocated_replicas.clone();
+    let mut fallback_replicas = vec![0; child_names.len()];
+    let mut last_fallback_times = vec![None; child_names.len()];
+    let mut terminating_replicas = vec![0; child_names.len()];
+    for (i, (child_name, child)) in obj.spec.children.iter().enumerate() {
+        let Some(fallback) = &child.fallback else {
+            continue;
+        };
+        let fallback_index = child_names
+            .iter()
+            .position(|&name| *name == fallback.child)
+            .unwrap();
+
+        let list_params = ListParams::default().labels(&format!(
+            "{}={}",
+            LABEL_SELECTOR_KEY,
+            render::deployment_name(&source_name, child_name)
         ));
+        let threshold = TimeDelta::seconds(
+            fallback
+                .unschedulable_seconds
+                .unwrap_or(DEFAULT_UNSCHEDULABLE_SECONDS),
+        );
+        let mut pod_count = 0;
+        let mut unschedulable = 0;
+        let mut node_names = Vec::new();
+        for pod in ctx.pods.list(&list_params).await? {
+            if !is_active(&pod) {
+                continue;
+            }
+            pod_count += 1;
+            if let Some(node_name) = pod.spec.as_ref().and_then(|s| s.node_name.clone()) {
+                node_names.push(node_name);
+            }
+            if let Some(since) = unschedulable_since(&pod) {
+                let waited = now - since;
+                if waited >= threshold {
+                    unschedulable += 1;
+                } else {
+                    requeue = earliest(requeue, threshold - waited);
+                }
+            }
+        }
+
+        let previous = previous_children.get(child_name);
+        let previous_moved = previous.and_then(|c| c.fallback_replicas).unwrap_or(0);
+        let last_fallback_time = previous.and_then(|c| c.last_fallback_time.clone());
+        let retry_after = TimeDelta::seconds(
+            fallback
+                .retry_seconds
+                .unwrap_or(DEFAULT_FALLBACK_RETRY_SECONDS),
+        );
+        let retry = last_fallback_time
+            .as_ref()
+            .is_some_and(|t| now - t.0 >= retry_after);
+
+        let mut moved = utils::fallback_replicas(
+            allocated_replicas[i],
+            pod_count,
+            unschedulable,
+            previous_moved.into(),
+            retry,
+        );
+        // the fallback child still honours its own maxReplicas
+        if let Some(max) = maximums[fallback_index] {
+            moved = moved.min((max - effective_replicas[fallback_index]).max(0));
+        }
+        if moved > 0 {
+            info!(
+                "Moving {} replicas of {} to fallback {}",
+                moved, child_name, fallback.child
+            );
+            // retries that still cannot schedule start a new retry window
+            let last = if moved > previous_moved.into() || retry {
+                Time(now)
+            } else {
+                last_fallback_time.unwrap_or(Time(now))
+            };
+            requeue = earliest(requeue, retry_after - (now - last.0));
+            last_fallback_times[i] = Some(last);
+        }
+
+        effective_replicas[i] -= moved;
+        effective_replicas[fallback_index] += moved;
+        fallback_replicas[i] = moved;
+
+        // warm up the fallback child for pods on nodes about to be terminated.
+        // Once those pods are gone this is dropped again, and the fallback
+        // child only shrinks when the overall availability allows it.
+        let termination_taints = fallback.termination_taints.as_deref().unwrap_or_default();
+        if let Some(nodes) = &ctx.nodes
+            && !termination_taints.is_empty()
+        {
+            let mut terminating = node_names
+                .iter()
+                .filter(|node_name| {
+                    nodes.get(&ObjectRef::new(node_name)).is_some_and(|node| {
+                        node_termination_taints(&node, termination_taints)
+                            .next()
+                            .is_some()
+                    })
+                })
+                .count() as i64;
+            if let Some(max) = maximums[fallback_index] {
+                terminating = terminating.min((max - effective_replicas[fallback_index]).max(0));
+            }
+            if terminating > 0 {
+                info!(
+                    "Starting {} replicas of {} on fallback {} ahead of node termination",
+                    terminating, child_name, fallback.child
+                );
+            }
+            effective_replicas[fallback_index] += terminating;
+            terminating_replicas[i] = terminating;
+        }
     }
 
-    // do allocation
-    let minimums: Vec<i64> = obj
-        .spec
-        .children
-        .values()
-        .map(|c| c.min_replicas.unwrap_or(0).into())
+    // hold children that haven't started yet at zero replicas until the
+    // children they depend on are available
+    let index = |name: &str| child_names.iter().position(|&c| c == name).unwrap();
+    let order: Vec<usize> = render::dependency_order(&obj)?
+        .iter()
+        .map(|child_name| index(child_name))
         .collect();
-    let weights: Vec<f64> = obj
+    let depends_on: Vec<Vec<usize>> = obj
         .spec
         .children
         .values()
-        .map(|c| c.weight.unwrap_or(0).into())
+        .map(|child| {
+            child
+                .depends_on
+                .iter()
+                .flatten()
+                .map(|d| index(d))
+                .collect()
+        })
         .collect();
-    let calculated_replicas =
-        utils::allocate_weighted_with_minima(total_replicas.into(), &minimums, &weights)?;
+    let required: Vec<i64> = (0..child_names.len())
+        .map(|j| allocation.minimums[j].max(1).min(effective_replicas[j]))
+        .collect();
+    let released: Vec<bool> = current_replicas.iter().map(|&c| c > 0).collect();
+    let waits = utils::dependency_waits(
+        &order,
+        &depends_on,
+        &required,
+        &available_replicas,
+        &released,
+    );
+    let mut waiting_for = vec![Vec::new(); child_names.len()];
+    for (i, child_name) in child_names.iter().enumerate() {
+        if waits[i].is_empty() {
+            continue;
+        }
+        waiting_for[i] = waits[i].iter().map(|&j| child_names[j].clone()).collect();
+        if effective_replicas[i] > 0 {
+            info!(
+                "Holding child deployment {} until {} are available",
+                child_name,
+                waiting_for[i].join(", ")
+            );
+            effective_replicas[i] = 0;
+            requeue = earliest(requeue, REALLOCATION_REQUEUE_INTERVAL);
+        }
+    }
+
+    // scale up gaining children first, and scale down losing children only
+    // as far as the available replicas allow
+    let step_replicas = utils::surge_safe_step(
+        &current_replicas,
+        &available_replicas,
+        &effective_replicas,
+        max_unavailable,
+    )?;
 
+    let mut patch_errors = vec![None; obj.spec.children.len()];
+    let mut rendered_deployments = Vec::with_capacity(obj.spec.children.len());
     for (i, child_name) in obj.spec.children.keys().enumerate() {
-        let replicas = Some(calculated_replicas[i] as i32);
-        let deployment_data = create_owned_deployment(&obj, child_name.clone(), replicas)?;
+        let replicas = Some(step_replicas[i] as i32);
+        match render::render_deployment(&obj, child_name, replicas, allocated_replicas[i], &options)
+        {
+            Ok(deployment_data) => rendered_deployments.push(Some(deployment_data)),
+            Err(Error::PatchError(message)) => {
+                // leave the Deployment as it is, other children still get reconciled
+                warn!("Skipping child deployment {}: {}", child_name, message);
+                patch_errors[i] = Some(message);
+                rendered_deployments.push(None);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // roll out pod template changes one child at a time: children after the
+    // one rolling out keep the template they run
+    let mut rollout_child = None;
+    let mut degraded = None;
+    if obj.spec.rollout.is_some() {
+        for child_name in render::rollout_order(&obj)? {
+            let i = obj
+                .spec
+                .children
+                .keys()
+                .position(|c| *c == child_name)
+                .unwrap();
+            // new children are created right away
+            let (Some(rendered), Some(live)) = (&mut rendered_deployments[i], &live_deployments[i])
+            else {
+                continue;
+            };
+            let current = rendered.annotations().get(render::TEMPLATE_HASH_ANNOTATION)
+                == live.annotations().get(render::TEMPLATE_HASH_ANNOTATION);
+            if rollout_child.is_some() {
+                if !current {
+                    hold_template(rendered, live)?;
+                }
+                continue;
+            }
+            if !current || !utils::rollout_complete(live) {
+                if current && utils::progress_deadline_exceeded(live) {
+                    warn!("Rollout of child deployment {} is stalled", child_name);
+                    degraded = Some(format!(
+                        "Rollout of child {} exceeded its progress deadline",
+                        child_name
+                    ));
+                }
+                rollout_child = Some(child_name);
+            }
+        }
+        if rollout_child.is_some() {
+            requeue = earliest(requeue, REALLOCATION_REQUEUE_INTERVAL);
+        }
+    }
+
+    // delete Deployments of expired children without replicas
+    for (i, child_name) in obj.spec.children.keys().enumerate() {
+        let expired = expiries.get(child_name).is_some_and(|e| e.expired);
+        if !expired || step_replicas[i] > 0 || current_replicas[i] > 0 {
+            continue;
+        }
+        rendered_deployments[i] = None;
+        if live_deployments[i].is_some() {
+            let name = render::deployment_name(&source_name, child_name);
+            info!("Deleting Deployment of expired child: {}", name);
+            deployments.delete(&name, &Default::default()).await?;
+            publish_event(
+                &ctx,
+                &obj,
+                "ChildPruned",
+                format!(
+                    "Deleted Deployment {} of expired child {}",
+                    name, child_name
+                ),
+            )
+            .await;
+        }
+    }
+
+    for deployment_data in rendered_deployments.into_iter().flatten() {
         let server_side = PatchParams::apply(CONTROLLER_NAME);
 
         // create or patch the Deployment
@@ -129,94 +483,391 @@ pub async fn reconcile(obj: Arc<MultiDeployment>, ctx: Arc<Context>) -> Result<A
             .await?;
     }
 
+    // delete Deployments of children that are no longer generated, once the
+    // remaining children are available, so that no capacity is lost in between
+    let uid = obj.uid();
+    let list_params = ListParams::default().labels(&format!(
+        "app.kubernetes.io/part-of={},{}=true",
+        source_name,
+        render::GENERATED_LABEL
+    ));
+    let stale_deployments: Vec<String> = deployments
+        .list(&list_params)
+        .await?
+        .into_iter()
+        .filter(|d| {
+            d.owner_references()
+                .iter()
+                .any(|o| Some(&o.uid) == uid.as_ref() && o.controller == Some(true))
+        })
+        .map(|d| d.name_any())
+        .filter(|name| {
+            !obj.spec
+                .children
+                .keys()
+                .any(|child_name| render::deployment_name(&source_name, child_name) == *name)
+        })
+        .collect();
+    if !stale_deployments.is_empty() {
+        let settled = step_replicas == effective_replicas
+            && available_replicas
+                .iter()
+                .zip(&effective_replicas)
+                .all(|(available, effective)| available >= effective);
+        if settled {
+            for name in &stale_deployments {
+                info!("Deleting Deployment of no longer generated child: {}", name);
+                deployments.delete(name, &Default::default()).await?;
+            }
+        } else {
+            requeue = earliest(requeue, REALLOCATION_REQUEUE_INTERVAL);
+        }
+    }
+
     let selector: Selector = obj.spec.root_template.selector.clone().try_into()?;
 
+    let children_status = obj
+        .spec
+        .children
+        .keys()
+        .enumerate()
+        .map(|(i, child_name)| {
+            let child_status = ChildStatus {
+                weight: obj.spec.children[child_name].weight,
+                replicas: allocated_replicas[i] as i32,
+                target_replicas: calculated_replicas[i] as i32,
+                effective_replicas: effective_replicas[i] as i32,
+                fallback_replicas: (fallback_replicas[i] > 0)
+                    .then_some(fallback_replicas[i] as i32),
+                last_fallback_time: last_fallback_times[i].clone(),
+                terminating_replicas: (terminating_replicas[i] > 0)
+                    .then_some(terminating_replicas[i] as i32),
+                patch_error: patch_errors[i].clone(),
+                waiting_for: (!waiting_for[i].is_empty()).then(|| waiting_for[i].clone()),
+                expires_at: expiries.get(child_name).map(|e| Time(e.expires_at)),
+                expired: expiries
+                    .get(child_name)
+                    .and_then(|e| e.expired.then_some(true)),
+            };
+            (child_name.clone(), child_status)
+        })
+        .collect();
+    let status = MultiDeploymentStatus {
+        replicas: Some(total_replicas),
+        selector: Some(selector.to_string()),
+        children: Some(children_status),
+        last_rebalance_time,
+        allocation_hash: Some(allocation_hash),
+        generated_children: (!generated_children.is_empty()).then_some(generated_children),
+        rollout_child,
+        conditions: degraded.map(|message| {
+            // keep the time the rollout got stalled
+            let since = previous_status
+                .conditions
+                .iter()
+                .flatten()
+                .find(|c| c.type_ == "Degraded" && c.status == "True")
+                .map(|c| c.last_transition_time.clone());
+            vec![Condition {
+                type_: "Degraded".to_string(),
+                status: "True".to_string(),
+                reason: "ProgressDeadlineExceeded".to_string(),
+                message,
+                last_transition_time: since.unwrap_or(Time(now)),
+                observed_generation: obj.metadata.generation,
+            }]
+        }),
+    };
+
     // patch status
     let status = serde_json::json!({
         "apiVersion": format!("{}/{}", RESOURCE_GROUP, RESOURCE_VERSION),
         "kind": RESOURCE_KIND,
-        "status": {
-            "replicas": total_replicas,
-            "selector": selector.to_string(),
-        },
+        "status": status,
     });
     let patch_params = PatchParams::apply(CONTROLLER_NAME).force();
     multi_deployments
         .patch_status(&obj.name_any(), &patch_params, &Patch::Apply(status))
         .await?;
 
-    Ok(Action::await_change())
-}
+    if step_replicas != effective_replicas {
+        info!(
+            "Reallocation of {} in progress, waiting for replicas to become available",
+            source_name
+        );
+        requeue = earliest(requeue, REALLOCATION_REQUEUE_INTERVAL);
+    }
 
-pub fn error_policy(_obj: Arc<MultiDeployment>, error: &Error, _ctx: Arc<Context>) -> Action {
-    error!("Reconciliation error: {:?}", error);
-    Action::requeue(Duration::from_secs(5 * 60))
+    match requeue {
+        Some(duration) => Ok(Action::requeue(duration.to_std().unwrap_or_default())),
+        None => Ok(Action::await_change()),
+    }
 }
 
-fn create_owned_deployment(
-    source: &MultiDeployment,
-    child_name: String,
-    replicas: Option<i32>,
-) -> Result<Deployment, Error> {
-    let oref = source.controller_owner_ref(&()).unwrap();
-    let source_name = source.name_any();
-    let child_deployment = source.spec.children.get(&child_name).unwrap();
-
-    // create unique selector based on source and child names
-    let mut new_selector = source.spec.root_template.selector.clone();
-    new_selector
-        .match_labels
-        .get_or_insert_with(|| BTreeMap::new())
-        .insert(
-            LABEL_SELECTOR_KEY.to_string(),
-            format!("{}-{}", source_name, child_name),
-        );
+/// Maps a node whose termination taints changed to the MultiDeployments
+/// reacting to them. `tainted_nodes` keeps the termination taints last seen
+/// on each node, so other node updates don't trigger reconciliations.
+pub fn multi_deployments_for_node(
+    store: &Store<MultiDeployment>,
+    tainted_nodes: &Mutex<BTreeMap<String, BTreeSet<String>>>,
+    node: &Node,
+) -> Vec<ObjectRef<MultiDeployment>> {
+    let termination_taints = |md: &MultiDeployment| -> Vec<String> {
+        md.spec
+            .children
+            .values()
+            .filter_map(|child| child.fallback.as_ref()?.termination_taints.clone())
+            .flatten()
+            .collect()
+    };
+    let multi_deployments = store.state();
+    let watched: Vec<String> = multi_deployments
+        .iter()
+        .flat_map(|md| termination_taints(md))
+        .collect();
+    let taints: BTreeSet<String> = node_termination_taints(node, &watched).cloned().collect();
 
-    // create new labels based on root template labels
-    let mut new_labels = source
-        .spec
-        .root_template
-        .template
-        .metadata
-        .as_ref()
-        .and_then(|m| m.labels.clone())
+    let mut tainted_nodes = tainted_nodes.lock().unwrap();
+    let previous = tainted_nodes
+        .get(&node.name_any())
+        .cloned()
         .unwrap_or_default();
-    new_labels.insert(
-        LABEL_SELECTOR_KEY.to_string(),
-        format!("{}-{}", source_name, child_name),
-    );
+    if taints == previous {
+        return Vec::new();
+    }
+    let changed: BTreeSet<&String> = taints.symmetric_difference(&previous).collect();
+    if taints.is_empty() {
+        tainted_nodes.remove(&node.name_any());
+    } else {
+        tainted_nodes.insert(node.name_any(), taints.clone());
+    }
+    multi_deployments
+        .into_iter()
+        .filter(|md| termination_taints(md).iter().any(|t| changed.contains(t)))
+        .map(|md| ObjectRef::from_obj(&*md))
+        .collect()
+}
 
-    // build child deployment spec
-    let child_deployment_data = DeploymentSpec {
-        selector: new_selector,
-        template: PodTemplateSpec {
-            metadata: Some(ObjectMeta {
-                labels: Some(new_labels),
-                ..Default::default()
-            }),
-            spec: Some(child_deployment.pod_spec.clone()),
-        },
-        replicas,
-        ..source.spec.root_template.clone()
-    };
+/// Keys of the taints of `node` that are among `termination_taints`.
+fn node_termination_taints<'a>(
+    node: &'a Node,
+    termination_taints: &'a [String],
+) -> impl Iterator<Item = &'a String> {
+    node.spec
+        .iter()
+        .flat_map(|s| s.taints.iter().flatten())
+        .map(|t| &t.key)
+        .filter(|key| termination_taints.contains(key))
+}
 
-    // merge two deployment specs
-    let mut root_spec = serde_json::to_value(&source.spec.root_template)?;
-    let child_spec = serde_json::to_value(&child_deployment_data)?;
+/// Maps a placement profile to the MultiDeployments whose children use it
+pub fn multi_deployments_for_placement_profile(
+    store: &Store<MultiDeployment>,
+    profile: &PlacementProfile,
+) -> Vec<ObjectRef<MultiDeployment>> {
+    let name = profile.name_any();
+    store
+        .state()
+        .into_iter()
+        .filter(|md| {
+            let inline = md
+                .spec
+                .placement_profiles
+                .as_ref()
+                .is_some_and(|p| p.contains_key(&name));
+            !inline
+                && md
+                    .spec
+                    .children
+                    .values()
+                    .any(|child| child.placement.as_ref() == Some(&name))
+        })
+        .map(|md| ObjectRef::from_obj(&*md))
+        .collect()
+}
 
-    json_patch::merge(&mut root_spec, &child_spec);
-    let root_spec = serde_json::from_value(root_spec)?;
+/// Maps a pod of a child with a fallback to the MultiDeployments that own it
+pub fn multi_deployments_for_pod(
+    store: &Store<MultiDeployment>,
+    pod: &Pod,
+) -> Vec<ObjectRef<MultiDeployment>> {
+    let Some(value) = pod.labels().get(LABEL_SELECTOR_KEY) else {
+        return Vec::new();
+    };
+    store
+        .state()
+        .into_iter()
+        .filter(|md| {
+            md.namespace() == pod.namespace()
+                && md.spec.children.iter().any(|(child_name, child)| {
+                    child.fallback.is_some()
+                        && render::deployment_name(&md.name_any(), child_name) == *value
+                })
+        })
+        .map(|md| ObjectRef::from_obj(&*md))
+        .collect()
+}
 
-    let deployment = Deployment {
-        metadata: ObjectMeta {
-            name: Some(format!("{}-{}", source_name, child_name)),
-            owner_references: Some(vec![oref]),
-            ..Default::default()
-        },
-        spec: Some(root_spec),
+/// Publishes an Event about `obj`. Failures are only logged.
+async fn publish_event(ctx: &Context, obj: &MultiDeployment, reason: &str, note: String) {
+    let event = Event {
+        type_: EventType::Normal,
+        reason: reason.to_string(),
+        note: Some(note),
+        action: "Reconcile".to_string(),
+        secondary: None,
+    };
+    if let Err(e) = ctx.recorder.publish(&event, &obj.object_ref(&())).await {
+        warn!("Failed to publish event {}: {}", reason, e);
+    }
+}
 
-        ..Default::default()
+/// Keeps the pod template `live` runs in `rendered`, as far as the controller
+/// applied it.
+fn hold_template(rendered: &mut Deployment, live: &Deployment) -> Result<(), Error> {
+    let owned = diff::owned_fields(
+        &serde_json::to_value(live)?,
+        &serde_json::to_value(&*rendered)?,
+    );
+    let template = match owned.pointer("/spec/template") {
+        Some(template) => serde_json::from_value(template.clone())?,
+        None => live.spec.clone().unwrap_or_default().template,
+    };
+    if let Some(spec) = &mut rendered.spec {
+        spec.template = template;
+    }
+    match live.annotations().get(render::TEMPLATE_HASH_ANNOTATION) {
+        Some(hash) => rendered
+            .annotations_mut()
+            .insert(render::TEMPLATE_HASH_ANNOTATION.to_string(), hash.clone()),
+        None => rendered
+            .annotations_mut()
+            .remove(render::TEMPLATE_HASH_ANNOTATION),
     };
+    Ok(())
+}
+
+fn earliest(requeue: Option<TimeDelta>, after: TimeDelta) -> Option<TimeDelta> {
+    Some(requeue.map_or(after, |r| r.min(after)))
+}
+
+/// Pods that are neither terminating nor finished. Evicted pods, e.g. after a
+/// graceful node shutdown, stay `Failed` until they are garbage collected,
+/// while their ReplicaSet has already replaced them.
+fn is_active(pod: &Pod) -> bool {
+    let finished = pod
+        .status
+        .as_ref()
+        .and_then(|s| s.phase.as_deref())
+        .is_some_and(|phase| phase == "Failed" || phase == "Succeeded");
+    pod.metadata.deletion_timestamp.is_none() && !finished
+}
+
+/// Time since a pending pod is known to be unschedulable
+fn unschedulable_since(pod: &Pod) -> Option<DateTime<Utc>> {
+    let status = pod.status.as_ref()?;
+    if status.phase.as_deref() != Some("Pending") {
+        return None;
+    }
+    let condition = status.conditions.as_ref()?.iter().find(|c| {
+        c.type_ == "PodScheduled"
+            && c.status == "False"
+            && c.reason.as_deref() == Some("Unschedulable")
+    })?;
+    condition
+        .last_transition_time
+        .as_ref()
+        .or(pod.metadata.creation_timestamp.as_ref())
+        .map(|t| t.0)
+}
 
-    Ok(deployment)
+pub fn error_policy(_obj: Arc<MultiDeployment>, error: &Error, _ctx: Arc<Context>) -> Action {
+    error!("Reconciliation error: {:?}", error);
+    Action::requeue(Duration::from_secs(5 * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::runtime::{reflector, watcher};
+
+    use super::*;
+
+    #[test]
+    fn ignores_evicted_pods() {
+        let pod = |phase: &str, unschedulable: bool| -> Pod {
+            let conditions = if unschedulable {
+                "[{type: PodScheduled, status: \"False\", reason: Unschedulable, lastTransitionTime: \"2025-01-01T00:00:00Z\"}]"
+            } else {
+                "[]"
+            };
+            serde_yaml::from_str(&format!(
+                "{{metadata: {{name: web}}, status: {{phase: {}, conditions: {}}}}}",
+                phase, conditions
+            ))
+            .unwrap()
+        };
+        let mut pods = Vec::new();
+        pods.extend((0..7).map(|_| pod("Running", false)));
+        pods.extend((0..3).map(|_| pod("Pending", true)));
+        pods.extend((0..3).map(|_| pod("Failed", false)));
+        pods.push(pod("Succeeded", false));
+
+        let active: Vec<&Pod> = pods.iter().filter(|pod| is_active(pod)).collect();
+        let unschedulable = active
+            .iter()
+            .filter(|pod| unschedulable_since(pod).is_some())
+            .count() as i64;
+        assert_eq!((active.len(), unschedulable), (10, 3));
+        assert_eq!(
+            utils::fallback_replicas(10, active.len() as i64, unschedulable, 0, false),
+            3
+        );
+    }
+
+    #[test]
+    fn maps_nodes_whose_termination_taints_change() {
+        let (store, mut writer) = reflector::store();
+        for (name, taints) in [
+            ("spot", "[aws-node-termination-handler/spot-itn]"),
+            ("plain", "[]"),
+        ] {
+            let md: MultiDeployment = serde_yaml::from_str(&format!(
+                "{{metadata: {{name: {name}, namespace: default}},
+                  spec: {{name: {name}, rootTemplate: {{selector: {{}}, template: {{}}}},
+                          children: {{a: {{fallback: {{child: b, terminationTaints: {taints}}}}}, b: {{}}}}}}}}"
+            ))
+            .unwrap();
+            writer.apply_watcher_event(&watcher::Event::Apply(md));
+        }
+        let node = |taints: &str| -> Node {
+            serde_yaml::from_str(&format!(
+                "{{metadata: {{name: node-1}}, spec: {{taints: {}}}}}",
+                taints
+            ))
+            .unwrap()
+        };
+        let tainted = node("[{key: aws-node-termination-handler/spot-itn, effect: NoSchedule}]");
+        let tainted_nodes = Mutex::new(BTreeMap::new());
+        let names = |refs: Vec<ObjectRef<MultiDeployment>>| -> Vec<String> {
+            refs.into_iter().map(|r| r.name).collect()
+        };
+
+        // other updates of an untainted node
+        assert!(multi_deployments_for_node(&store, &tainted_nodes, &node("[]")).is_empty());
+        // tainted, then unchanged status updates
+        assert_eq!(
+            names(multi_deployments_for_node(&store, &tainted_nodes, &tainted)),
+            ["spot"]
+        );
+        assert!(multi_deployments_for_node(&store, &tainted_nodes, &tainted).is_empty());
+        // the taint is removed again
+        assert_eq!(
+            names(multi_deployments_for_node(
+                &store,
+                &tainted_nodes,
+                &node("[]")
+            )),
+            ["spot"]
+        );
+    }
 }
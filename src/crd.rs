@@ -1,13 +1,20 @@
 use std::collections::BTreeMap;
 
 use k8s_openapi::{
-    api::{apps::v1::DeploymentSpec, core::v1::PodSpec},
+    api::{
+        apps::v1::{DeploymentSpec, DeploymentStrategy},
+        core::v1::{Affinity, PodSpec, Toleration, TopologySpreadConstraint},
+    },
+    apimachinery::pkg::{
+        apis::meta::v1::{Condition, Time},
+        util::intstr::IntOrString,
+    },
     serde::{Deserialize, Serialize},
 };
 use kube::CustomResource;
 use schemars::JsonSchema;
 
-#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
 #[kube(
     kind = "MultiDeployment",
     group = "skystar.dev",
@@ -27,20 +34,393 @@ pub struct MultiDeploymentSpec {
     #[serde(rename = "rootTemplate")]
     pub root_template: DeploymentSpec,
     pub children: BTreeMap<String, ChildDeployment>,
+
+    /// How replicas are split between children. Defaults to `Weighted`.
+    #[serde(rename = "allocationStrategy", skip_serializing_if = "Option::is_none")]
+    pub allocation_strategy: Option<AllocationStrategy>,
+    /// What weights are split by. Defaults to `Replicas`.
+    #[serde(rename = "weightBasis", skip_serializing_if = "Option::is_none")]
+    pub weight_basis: Option<WeightBasis>,
+    /// Resource whose requests are split by weight with `weightBasis: Resources`,
+    /// e.g. `cpu` or `memory`. Defaults to `cpu`.
+    #[serde(rename = "weightResource", skip_serializing_if = "Option::is_none")]
+    pub weight_resource: Option<String>,
+    /// How weighted shares are rounded to whole replicas. Defaults to
+    /// `LargestRemainder`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rounding: Option<RoundingMode>,
+
+    /// Maximum number (or percentage of `replicas`) of pods that may be
+    /// unavailable while replicas are moved between children. Children gaining
+    /// replicas are always scaled up first. Defaults to 0.
+    #[serde(rename = "maxUnavailable", skip_serializing_if = "Option::is_none")]
+    pub max_unavailable: Option<IntOrString>,
+
+    /// Limits how fast replicas are moved between children when weights change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rebalance: Option<RebalancePolicy>,
+
+    /// Copies labels and annotations of the MultiDeployment to the child
+    /// Deployments. Nothing is copied by default.
+    #[serde(
+        rename = "metadataPropagation",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub metadata_propagation: Option<MetadataPropagation>,
+
+    /// Substitutes `{{ variable }}` in the strings of the pod template when
+    /// true. Off by default, so that templates of other tools such as Go
+    /// templates are passed through unchanged.
+    #[serde(rename = "templateVariables", skip_serializing_if = "Option::is_none")]
+    pub template_variables: Option<bool>,
+
+    /// Placement profiles children can refer to by name. They take precedence
+    /// over cluster-wide `PlacementProfile` resources of the same name.
+    #[serde(rename = "placementProfiles", skip_serializing_if = "Option::is_none")]
+    pub placement_profiles: Option<BTreeMap<String, PlacementProfileSpec>>,
+
+    /// Generate additional children from lists or matrices of parameters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generators: Option<Vec<ChildGenerator>>,
+
+    /// Stops reconciling the child Deployments while true, e.g. during an
+    /// incident. They keep their current replicas and templates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paused: Option<bool>,
+
+    /// Restarts the pods of all children when set to a later time, like
+    /// `kubectl rollout restart` does.
+    #[serde(rename = "restartedAt", skip_serializing_if = "Option::is_none")]
+    pub restarted_at: Option<Time>,
+
+    /// Rolls out pod template changes one child at a time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rollout: Option<RolloutPolicy>,
+}
+
+/// Generates one child per parameter set of either `list` or `matrix`.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct ChildGenerator {
+    /// Parameter sets, e.g. `[{zone: a}, {zone: b}]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub list: Option<Vec<BTreeMap<String, String>>>,
+    /// Every combination of the values, e.g. `{zone: [a, b], capacity: [spot, on-demand]}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<BTreeMap<String, Vec<String>>>,
+    /// Name of the generated children, e.g. `"{{ zone }}-{{ capacity }}"`.
+    #[serde(rename = "nameTemplate")]
+    pub name_template: String,
+    /// Child with `{{ parameter }}` replaced by the parameters.
+    pub template: ChildDeployment,
+}
+
+/// Where pods of a child are scheduled, e.g. on spot or on-demand nodes.
+/// Merged onto the root pod spec before the child's `podSpec`.
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[kube(kind = "PlacementProfile", group = "skystar.dev", version = "v1")]
+pub struct PlacementProfileSpec {
+    #[serde(rename = "nodeSelector", skip_serializing_if = "Option::is_none")]
+    pub node_selector: Option<BTreeMap<String, String>>,
+    /// Replaces the tolerations of the root template.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tolerations: Option<Vec<Toleration>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub affinity: Option<Affinity>,
+    #[serde(
+        rename = "topologySpreadConstraints",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub topology_spread_constraints: Option<Vec<TopologySpreadConstraint>>,
+    #[serde(rename = "priorityClassName", skip_serializing_if = "Option::is_none")]
+    pub priority_class_name: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct MetadataPropagation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<PropagationRule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<PropagationRule>,
+    /// Also copy to the pod template. Changes then roll out new pods.
+    #[serde(rename = "podTemplate", skip_serializing_if = "Option::is_none")]
+    pub pod_template: Option<bool>,
+}
+
+/// Keys are copied when they start with one of `allow` (all keys if not set)
+/// and with none of `deny`. `kubectl.kubernetes.io/` keys are never copied.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct PropagationRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deny: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum AllocationStrategy {
+    /// Split replicas by weight, raising children to their minimum where needed.
+    #[default]
+    Weighted,
+    /// Give every child its minimum, then fill children in descending
+    /// `priority` order up to their `maxReplicas`.
+    PriorityFill,
+    /// Give every child its minimum, then split the rest by weight.
+    FixedThenWeighted,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum WeightBasis {
+    /// Weights split the replica count.
+    #[default]
+    Replicas,
+    /// Weights split the requests of `weightResource` across children, so
+    /// children with bigger pods get fewer replicas.
+    Resources,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum RoundingMode {
+    /// Give leftover replicas to the largest fractional shares.
+    #[default]
+    LargestRemainder,
+    /// Stay within one replica of the ideal share while moving as few
+    /// replicas as possible away from the previous allocation.
+    Stable,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct RebalancePolicy {
+    /// Maximum number (or percentage of `replicas`) of replicas moved between
+    /// children per interval. At least one replica is moved per interval.
+    #[serde(rename = "maxReplicas")]
+    pub max_replicas: IntOrString,
+    /// Seconds between rebalancing steps. Defaults to 60.
+    #[serde(rename = "intervalSeconds", skip_serializing_if = "Option::is_none")]
+    pub interval_seconds: Option<i64>,
+}
+
+/// Children whose pod template changed are updated one after the other: the
+/// next child is only updated once the Deployment of the previous one has
+/// fully rolled out. A child whose rollout exceeds its progress deadline halts
+/// the rollout and marks the MultiDeployment `Degraded`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct RolloutPolicy {
+    /// Children in the order they are updated. Children not listed follow in
+    /// name order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
 pub struct MultiDeploymentStatus {
-    replicas: Option<i32>,
-    selector: Option<String>,
+    pub replicas: Option<i32>,
+    pub selector: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub children: Option<BTreeMap<String, ChildStatus>>,
+    #[serde(rename = "lastRebalanceTime", skip_serializing_if = "Option::is_none")]
+    pub last_rebalance_time: Option<Time>,
+    /// Hash of the inputs `targetReplicas` were allocated with, besides
+    /// `replicas`. Scale priorities only apply while it's unchanged.
+    #[serde(rename = "allocationHash", skip_serializing_if = "Option::is_none")]
+    pub allocation_hash: Option<String>,
+    /// Children generated by `generators`.
+    #[serde(rename = "generatedChildren", skip_serializing_if = "Option::is_none")]
+    pub generated_children: Option<Vec<String>>,
+    /// Child whose pod template is being rolled out with `rollout`.
+    #[serde(rename = "rolloutChild", skip_serializing_if = "Option::is_none")]
+    pub rollout_child: Option<String>,
+    /// `Degraded` while a child rolled out with `rollout` exceeds its
+    /// progress deadline.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conditions: Option<Vec<Condition>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct ChildStatus {
+    /// Weight the allocation was made with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<i32>,
+    /// Replicas currently allocated to the child, on the way to `targetReplicas`.
+    pub replicas: i32,
+    /// Replicas the child ends up with once rebalancing is done.
+    #[serde(rename = "targetReplicas")]
+    pub target_replicas: i32,
+    /// Replicas actually assigned to the child, after replicas were moved to
+    /// or from fallback children.
+    #[serde(rename = "effectiveReplicas")]
+    pub effective_replicas: i32,
+    /// Replicas currently moved to the fallback child.
+    #[serde(rename = "fallbackReplicas", skip_serializing_if = "Option::is_none")]
+    pub fallback_replicas: Option<i32>,
+    #[serde(rename = "lastFallbackTime", skip_serializing_if = "Option::is_none")]
+    pub last_fallback_time: Option<Time>,
+    /// Replicas on nodes about to be terminated, additionally started on the
+    /// fallback child.
+    #[serde(
+        rename = "terminatingReplicas",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub terminating_replicas: Option<i32>,
+    /// Why `patches` could not be applied. The Deployment is left untouched
+    /// until they are fixed.
+    #[serde(rename = "patchError", skip_serializing_if = "Option::is_none")]
+    pub patch_error: Option<String>,
+    /// Dependencies the child is held at zero replicas for.
+    #[serde(rename = "waitingFor", skip_serializing_if = "Option::is_none")]
+    pub waiting_for: Option<Vec<String>>,
+    /// When the child expires with `expiresAt` or `ttl`.
+    #[serde(rename = "expiresAt", skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<Time>,
+    /// The child expired and is scaled down before its Deployment is deleted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expired: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 pub struct ChildDeployment {
     pub weight: Option<i32>,
+    /// Lower bound of replicas for this child, either a number or a
+    /// percentage of `replicas` (rounded up), e.g. "10%".
     #[serde(rename = "minReplicas")]
-    pub min_replicas: Option<i32>,
+    pub min_replicas: Option<IntOrString>,
+    /// Pins the child to this many replicas, outside of the weighted pool.
+    /// They still count towards `replicas`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replicas: Option<i32>,
+    /// Upper bound of replicas for this child, unbounded if not set.
+    #[serde(rename = "maxReplicas", skip_serializing_if = "Option::is_none")]
+    pub max_replicas: Option<i32>,
+    /// Fill order for the `PriorityFill` strategy, highest first. Defaults to 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// When `replicas` grows, children with higher priority gain replicas
+    /// first, up to their weighted share. Defaults to 0.
+    #[serde(rename = "scaleUpPriority", skip_serializing_if = "Option::is_none")]
+    pub scale_up_priority: Option<i32>,
+    /// When `replicas` shrinks, children with higher priority lose replicas
+    /// first, down to their minimum. Defaults to 0.
+    #[serde(rename = "scaleDownPriority", skip_serializing_if = "Option::is_none")]
+    pub scale_down_priority: Option<i32>,
+    /// Moves replicas whose pods cannot be scheduled to another child.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<ChildFallback>,
+    /// Name of a placement profile merged into the pod spec of this child.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub placement: Option<String>,
+    /// Restarts the pods of this child when set to a later time. The later of
+    /// this and `spec.restartedAt` applies.
+    #[serde(rename = "restartedAt", skip_serializing_if = "Option::is_none")]
+    pub restarted_at: Option<Time>,
+    /// Children that must be available before this child gets replicas: at
+    /// least their minimum replicas, or one replica without a minimum.
+    #[serde(rename = "dependsOn", skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<Vec<String>>,
+    /// Time after which the child is scaled down and its Deployment deleted,
+    /// e.g. for preview builds.
+    #[serde(rename = "expiresAt", skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<Time>,
+    /// Like `expiresAt`, counted from the creation of the child's Deployment,
+    /// e.g. `72h` or `1h30m`. Units are `s`, `m`, `h` and `d`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+
+    /// Labels and annotations added to the pod template.
+    #[serde(rename = "podMetadata", skip_serializing_if = "Option::is_none")]
+    pub pod_metadata: Option<ChildMetadata>,
+    /// Labels and annotations of the generated Deployment.
+    #[serde(rename = "deploymentMetadata", skip_serializing_if = "Option::is_none")]
+    pub deployment_metadata: Option<ChildMetadata>,
+    /// Replaces `rootTemplate.strategy`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<DeploymentStrategy>,
+    #[serde(rename = "minReadySeconds", skip_serializing_if = "Option::is_none")]
+    pub min_ready_seconds: Option<i32>,
+    #[serde(
+        rename = "progressDeadlineSeconds",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub progress_deadline_seconds: Option<i32>,
+    #[serde(
+        rename = "revisionHistoryLimit",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub revision_history_limit: Option<i32>,
+    /// Strategic-merged onto `rootTemplate.template.spec`: containers,
+    /// volumes, env etc. are merged by name, so only differences are needed.
+    #[serde(rename = "podSpec", default, skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "partial_pod_spec")]
+    pub pod_spec: Option<PodSpec>,
+    /// RFC 6902 operations applied in order to the rendered Deployment, after
+    /// `podSpec` was merged. e.g. `{op: remove, path: /spec/template/spec/containers/0/args/1}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patches: Option<Vec<JsonPatchOperation>>,
+}
 
-    #[serde(rename = "podSpec")]
-    pub pod_spec: PodSpec,
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct ChildMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct JsonPatchOperation {
+    pub op: JsonPatchOp,
+    pub path: String,
+    /// Value for `add`, `replace` and `test`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "any_value")]
+    pub value: Option<serde_json::Value>,
+    /// Source path for `move` and `copy`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonPatchOp {
+    Add,
+    Remove,
+    Replace,
+    Move,
+    Copy,
+    Test,
+}
+
+/// Schema of an arbitrary JSON value.
+fn any_value(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    schemars::json_schema!({ "x-kubernetes-preserve-unknown-fields": true })
+}
+
+/// Schema of a `PodSpec` overlay, which does not need to list containers.
+fn partial_pod_spec(generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    let mut schema = generator.subschema_for::<PodSpec>();
+    if let Some(object) = schema.as_object_mut() {
+        object.remove("required");
+    }
+    schema
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ChildFallback {
+    /// Child that temporarily takes over replicas this child cannot schedule.
+    pub child: String,
+    /// Seconds a pod has to be unschedulable before its replica is moved.
+    /// Defaults to 120.
+    #[serde(
+        rename = "unschedulableSeconds",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub unschedulable_seconds: Option<i64>,
+    /// Seconds after which moved replicas are tried on this child again.
+    /// Defaults to 600.
+    #[serde(rename = "retrySeconds", skip_serializing_if = "Option::is_none")]
+    pub retry_seconds: Option<i64>,
+    /// Taint keys marking nodes about to be terminated (e.g. spot interruption
+    /// notices). Replicas on such nodes are additionally started on the
+    /// fallback child. Requires the controller to watch nodes.
+    #[serde(rename = "terminationTaints", skip_serializing_if = "Option::is_none")]
+    pub termination_taints: Option<Vec<String>>,
 }
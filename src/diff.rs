@@ -0,0 +1,439 @@
+//! Differences between rendered child Deployments and the live ones, limited
+//! to the fields the controller owns.
+
+use std::collections::BTreeSet;
+
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::ResourceExt;
+use serde_json::{Map, Value};
+
+use crate::{
+    crd::MultiDeployment,
+    render::{self, CONTROLLER_NAME, GENERATED_LABEL, Rendered},
+    types::Error,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// The Deployment does not exist yet.
+    Create(String),
+    /// Owned fields of the Deployment change.
+    Modify(String, Vec<FieldChange>),
+    /// The Deployment belongs to a child that expired or is no longer
+    /// generated, the controller deletes it.
+    Prune(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    /// e.g. `spec.template.spec.containers[name=app].image`
+    pub path: String,
+    pub live: Option<Value>,
+    pub rendered: Option<Value>,
+}
+
+/// Compares the `rendered` Deployments of `source` with the `live` ones.
+/// Live Deployments of other owners are ignored, and so are those of children
+/// removed from `spec.children`, which the controller leaves in place.
+pub fn diff_deployments(
+    source: &MultiDeployment,
+    rendered: &Rendered,
+    live: &[Deployment],
+) -> Result<Vec<Change>, Error> {
+    let mut changes = Vec::new();
+    for deployment in &rendered.deployments {
+        let name = deployment.name_any();
+        let rendered = serde_json::to_value(deployment)?;
+        match live.iter().find(|d| d.name_any() == name) {
+            None => changes.push(Change::Create(name)),
+            Some(live) => {
+                let live = owned_fields(&serde_json::to_value(live)?, &rendered);
+                let mut fields = Vec::new();
+                diff_values("", Some(&live), Some(&rendered), &mut fields);
+                if !fields.is_empty() {
+                    changes.push(Change::Modify(name, fields));
+                }
+            }
+        }
+    }
+
+    let source_name = source.name_any();
+    let deployment_names = |children: &[String]| -> BTreeSet<String> {
+        children
+            .iter()
+            .map(|child_name| render::deployment_name(&source_name, child_name))
+            .collect()
+    };
+    let children = deployment_names(&rendered.allocation.children);
+    let expired = deployment_names(&rendered.expired_children);
+    for deployment in live {
+        let owned = deployment.owner_references().iter().any(|o| {
+            o.kind == "MultiDeployment" && o.name == source_name && o.controller == Some(true)
+        });
+        let name = deployment.name_any();
+        let generated = deployment
+            .labels()
+            .get(GENERATED_LABEL)
+            .is_some_and(|v| v == "true");
+        if owned && (expired.contains(&name) || generated && !children.contains(&name)) {
+            changes.push(Change::Prune(name));
+        }
+    }
+    Ok(changes)
+}
+
+/// The fields of `live` applied by the controller, according to its managed
+/// fields. Without managed fields (e.g. exported with kubectl, which hides
+/// them), the fields present in `rendered` are used instead.
+pub fn owned_fields(live: &Value, rendered: &Value) -> Value {
+    let managed = live
+        .pointer("/metadata/managedFields")
+        .and_then(Value::as_array)
+        .and_then(|entries| {
+            entries.iter().find(|entry| {
+                entry.get("manager").and_then(Value::as_str) == Some(CONTROLLER_NAME)
+                    && entry.get("operation").and_then(Value::as_str) == Some("Apply")
+                    && entry.get("subresource").is_none()
+            })
+        })
+        .and_then(|entry| entry.get("fieldsV1"));
+    let mut owned = match managed {
+        Some(fields) => project_managed(live, fields),
+        None => project_shape(live, rendered),
+    };
+    // type information is always part of an applied object
+    if let Value::Object(owned) = &mut owned {
+        for field in ["apiVersion", "kind"] {
+            if let Some(value) = live.get(field) {
+                owned.insert(field.to_string(), value.clone());
+            }
+        }
+        if let Some(Value::Object(metadata)) = owned.get_mut("metadata")
+            && let Some(name) = live.pointer("/metadata/name")
+        {
+            metadata.insert("name".to_string(), name.clone());
+        }
+    }
+    owned
+}
+
+/// Projects `value` onto a FieldsV1 set, e.g. `{"f:spec": {"f:replicas": {}}}`.
+fn project_managed(value: &Value, fields: &Value) -> Value {
+    let Some(fields) = fields.as_object().filter(|f| f.keys().any(|k| k != ".")) else {
+        // a leaf, or an item owned as a whole
+        return value.clone();
+    };
+    match value {
+        Value::Object(object) => {
+            let mut projected = Map::new();
+            for (key, sub) in fields {
+                if let Some(name) = key.strip_prefix("f:")
+                    && let Some(field) = object.get(name)
+                {
+                    projected.insert(name.to_string(), project_managed(field, sub));
+                }
+            }
+            Value::Object(projected)
+        }
+        Value::Array(items) => {
+            let mut projected = Vec::new();
+            for item in items {
+                let owned = fields.iter().find(|(key, _)| list_key_matches(key, item));
+                if let Some((_, sub)) = owned {
+                    projected.push(project_managed(item, sub));
+                }
+            }
+            for (key, sub) in fields {
+                let index = key.strip_prefix("i:").and_then(|i| i.parse::<usize>().ok());
+                if let Some(item) = index.and_then(|i| items.get(i)) {
+                    projected.push(project_managed(item, sub));
+                }
+            }
+            Value::Array(projected)
+        }
+        _ => value.clone(),
+    }
+}
+
+/// Whether a `k:{...}` or `v:...` key of a FieldsV1 list refers to `item`.
+fn list_key_matches(key: &str, item: &Value) -> bool {
+    if let Some(keys) = key.strip_prefix("k:") {
+        let Ok(Value::Object(keys)) = serde_json::from_str::<Value>(keys) else {
+            return false;
+        };
+        keys.iter().all(|(k, v)| item.get(k) == Some(v))
+    } else if let Some(set_value) = key.strip_prefix("v:") {
+        serde_json::from_str::<Value>(set_value).is_ok_and(|v| v == *item)
+    } else {
+        false
+    }
+}
+
+/// Projects `value` onto the fields present in `shape`.
+fn project_shape(value: &Value, shape: &Value) -> Value {
+    match (value, shape) {
+        (Value::Object(object), Value::Object(shape)) => Value::Object(
+            shape
+                .iter()
+                .filter_map(|(key, sub)| Some((key.clone(), project_shape(object.get(key)?, sub))))
+                .collect(),
+        ),
+        (Value::Array(items), Value::Array(shape)) => Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let sub = item_key(item)
+                        .and_then(|k| shape.iter().find(|s| item_key(s) == Some(k)))
+                        .or_else(|| shape.get(i));
+                    match sub {
+                        Some(sub) => project_shape(item, sub),
+                        None => item.clone(),
+                    }
+                })
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
+
+/// Key identifying an item of a list, for lists merged by name.
+fn item_key(item: &Value) -> Option<&Value> {
+    item.get("name")
+}
+
+fn diff_values(
+    path: &str,
+    live: Option<&Value>,
+    rendered: Option<&Value>,
+    changes: &mut Vec<FieldChange>,
+) {
+    match (live, rendered) {
+        (Some(Value::Object(live)), Some(Value::Object(rendered))) => {
+            let keys: BTreeSet<&String> = live.keys().chain(rendered.keys()).collect();
+            for key in keys {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                diff_values(&path, live.get(key), rendered.get(key), changes);
+            }
+        }
+        (Some(Value::Array(live)), Some(Value::Array(rendered)))
+            if live
+                .iter()
+                .chain(rendered)
+                .all(|item| item_key(item).is_some()) =>
+        {
+            // lists merged by name are compared item by item
+            let mut keys: Vec<&Value> = Vec::new();
+            for item in live.iter().chain(rendered) {
+                let key = item_key(item).unwrap();
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+            for key in keys {
+                let name = key.as_str().map_or_else(|| key.to_string(), str::to_string);
+                diff_values(
+                    &format!("{}[name={}]", path, name),
+                    live.iter().find(|item| item_key(item) == Some(key)),
+                    rendered.iter().find(|item| item_key(item) == Some(key)),
+                    changes,
+                );
+            }
+        }
+        (live, rendered) if live != rendered => changes.push(FieldChange {
+            path: path.to_string(),
+            live: live.cloned(),
+            rendered: rendered.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn deployment(value: Value) -> Deployment {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn source() -> MultiDeployment {
+        serde_json::from_value(json!({
+            "apiVersion": "skystar.dev/v1",
+            "kind": "MultiDeployment",
+            "metadata": {"name": "web"},
+            "spec": {"name": "web", "rootTemplate": {"selector": {}, "template": {}}, "children": {}},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn projects_managed_fields() {
+        let live = json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": {
+                "name": "web-a",
+                "labels": {"team": "web", "added-by-someone": "x"},
+                "managedFields": [
+                    {
+                        "manager": "multi-deployment-controller",
+                        "operation": "Apply",
+                        "fieldsV1": {
+                            "f:metadata": {"f:labels": {"f:team": {}}},
+                            "f:spec": {
+                                "f:replicas": {},
+                                "f:template": {"f:spec": {"f:containers": {
+                                    "k:{\"name\":\"app\"}": {".": {}, "f:image": {}, "f:name": {}},
+                                }}},
+                            },
+                        },
+                    },
+                    {
+                        "manager": "kubectl",
+                        "operation": "Update",
+                        "fieldsV1": {"f:metadata": {"f:labels": {"f:added-by-someone": {}}}},
+                    },
+                ],
+            },
+            "spec": {
+                "replicas": 2,
+                "revisionHistoryLimit": 10,
+                "template": {"spec": {"containers": [
+                    {"name": "app", "image": "web:1.0", "imagePullPolicy": "IfNotPresent"},
+                    {"name": "injected", "image": "sidecar:1.0"},
+                ]}},
+            },
+        });
+
+        assert_eq!(
+            owned_fields(&live, &json!({})),
+            json!({
+                "apiVersion": "apps/v1",
+                "kind": "Deployment",
+                "metadata": {"name": "web-a", "labels": {"team": "web"}},
+                "spec": {
+                    "replicas": 2,
+                    "template": {"spec": {"containers": [{"name": "app", "image": "web:1.0"}]}},
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn projects_rendered_shape_without_managed_fields() {
+        let live = json!({
+            "metadata": {"name": "web-a", "uid": "1234"},
+            "spec": {
+                "replicas": 2,
+                "revisionHistoryLimit": 10,
+                "template": {"spec": {"containers": [
+                    {"name": "app", "image": "web:1.0", "imagePullPolicy": "IfNotPresent"},
+                ]}},
+            },
+        });
+        let rendered = json!({
+            "metadata": {"name": "web-a"},
+            "spec": {"replicas": 3, "template": {"spec": {"containers": [{"name": "app", "image": "web:1.1"}]}}},
+        });
+
+        assert_eq!(
+            owned_fields(&live, &rendered),
+            json!({
+                "metadata": {"name": "web-a"},
+                "spec": {"replicas": 2, "template": {"spec": {"containers": [{"name": "app", "image": "web:1.0"}]}}},
+            })
+        );
+    }
+
+    #[test]
+    fn diffs_created_modified_and_pruned() {
+        let owner = json!([{
+            "apiVersion": "skystar.dev/v1",
+            "kind": "MultiDeployment",
+            "name": "web",
+            "uid": "1234",
+            "controller": true,
+        }]);
+        let deployments = vec![
+            deployment(json!({"metadata": {"name": "web-a"}, "spec": {
+                "replicas": 3,
+                "selector": {},
+                "template": {"spec": {"containers": [{"name": "app", "image": "web:1.1"}]}},
+            }})),
+            deployment(
+                json!({"metadata": {"name": "web-b"}, "spec": {"selector": {}, "template": {}}}),
+            ),
+        ];
+        let rendered = Rendered {
+            allocation: render::Allocation {
+                children: ["a", "b", "expired"].map(String::from).to_vec(),
+                minimums: vec![0; 3],
+                maximums: vec![None; 3],
+                replicas: vec![3, 0, 0],
+                inputs_hash: String::new(),
+            },
+            deployments,
+            patch_errors: Default::default(),
+            generated_children: Vec::new(),
+            expired_children: vec!["expired".to_string()],
+        };
+        let live = [
+            deployment(
+                json!({"metadata": {"name": "web-a", "ownerReferences": owner}, "spec": {
+                    "replicas": 2,
+                    "selector": {},
+                    "template": {"spec": {"containers": [{"name": "app", "image": "web:1.0"}]}},
+                }}),
+            ),
+            // no longer generated
+            deployment(json!({"metadata": {
+                "name": "web-old",
+                "ownerReferences": owner,
+                "labels": {GENERATED_LABEL: "true"},
+            }, "spec": {"selector": {}, "template": {}}})),
+            // removed from children, left in place
+            deployment(
+                json!({"metadata": {"name": "web-removed", "ownerReferences": owner}, "spec": {"selector": {}, "template": {}}}),
+            ),
+            deployment(
+                json!({"metadata": {"name": "web-expired", "ownerReferences": owner}, "spec": {"selector": {}, "template": {}}}),
+            ),
+            deployment(
+                json!({"metadata": {"name": "unrelated"}, "spec": {"selector": {}, "template": {}}}),
+            ),
+        ];
+
+        let changes = diff_deployments(&source(), &rendered, &live).unwrap();
+
+        assert_eq!(
+            changes,
+            [
+                Change::Modify(
+                    "web-a".to_string(),
+                    vec![
+                        FieldChange {
+                            path: "spec.replicas".to_string(),
+                            live: Some(json!(2)),
+                            rendered: Some(json!(3)),
+                        },
+                        FieldChange {
+                            path: "spec.template.spec.containers[name=app].image".to_string(),
+                            live: Some(json!("web:1.0")),
+                            rendered: Some(json!("web:1.1")),
+                        },
+                    ]
+                ),
+                Change::Create("web-b".to_string()),
+                Change::Prune("web-old".to_string()),
+                Change::Prune("web-expired".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::{
+    crd::{ChildDeployment, ChildGenerator, MultiDeploymentSpec},
+    render::GENERATED_LABEL,
+    template::{self, TemplateError},
+    types::Error,
+};
+
+/// Children of `spec` with the children of its generators added, and the
+/// names of the generated children.
+pub fn expand_children(
+    spec: &MultiDeploymentSpec,
+) -> Result<(BTreeMap<String, ChildDeployment>, Vec<String>), Error> {
+    let mut children = spec.children.clone();
+    let mut generated = Vec::new();
+    for generator in spec.generators.iter().flatten() {
+        for parameters in parameter_sets(generator)? {
+            let variables: BTreeMap<&str, String> = parameters
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.clone()))
+                .collect();
+            let name = template::render_str(&generator.name_template, &variables)
+                .map_err(|e| Error::ValidationError(format!("Generator name template: {}", e)))?;
+            if !is_valid_name(&name) {
+                return Err(Error::ValidationError(format!(
+                    "Generated child name {:?} must consist of lower case alphanumeric characters or '-'",
+                    name
+                )));
+            }
+            if children.contains_key(&name) {
+                return Err(Error::ValidationError(format!(
+                    "Generated child {} collides with another child",
+                    name
+                )));
+            }
+
+            let mut child = serde_json::to_value(&generator.template)?;
+            render_child(
+                &mut child,
+                &variables,
+                spec.template_variables == Some(true),
+            )
+            .map_err(|e| Error::ValidationError(format!("Generated child {}: {}", name, e)))?;
+            let mut child: ChildDeployment = serde_json::from_value(child)?;
+            child
+                .deployment_metadata
+                .get_or_insert_default()
+                .labels
+                .get_or_insert_default()
+                .insert(GENERATED_LABEL.to_string(), "true".to_string());
+            children.insert(name.clone(), child);
+            generated.push(name);
+        }
+    }
+    Ok((children, generated))
+}
+
+/// Renders the parameters into a generated child. With `template_variables`,
+/// other variables in the pod template are kept and rendered with the
+/// Deployment. Everything else must be rendered completely, like the strings
+/// of hand-written children.
+fn render_child(
+    child: &mut Value,
+    variables: &BTreeMap<&str, String>,
+    template_variables: bool,
+) -> Result<(), TemplateError> {
+    let Value::Object(fields) = child else {
+        return template::render(child, variables);
+    };
+    for (field, value) in fields.iter_mut() {
+        if template_variables && (field == "podSpec" || field == "podMetadata") {
+            template::render_partial(value, variables)?;
+        } else {
+            template::render(value, variables)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parameters of each child of a generator.
+fn parameter_sets(generator: &ChildGenerator) -> Result<Vec<BTreeMap<String, String>>, Error> {
+    match (&generator.list, &generator.matrix) {
+        (Some(list), None) => Ok(list.clone()),
+        (None, Some(matrix)) => {
+            // cartesian product of all values
+            let mut sets = vec![BTreeMap::new()];
+            for (name, values) in matrix {
+                sets = sets
+                    .into_iter()
+                    .flat_map(|set| {
+                        values.iter().map(move |value| {
+                            let mut set = set.clone();
+                            set.insert(name.clone(), value.clone());
+                            set
+                        })
+                    })
+                    .collect();
+            }
+            Ok(sets)
+        }
+        _ => Err(Error::ValidationError(
+            "Generator must have exactly one of list or matrix".to_string(),
+        )),
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(generator: &str) -> MultiDeploymentSpec {
+        serde_yaml::from_str(&format!(
+            "{{name: web, rootTemplate: {{selector: {{}}, template: {{}}}}, children: {{base: {{weight: 1}}}}, generators: [{}]}}",
+            generator
+        ))
+        .unwrap()
+    }
+
+    fn spec_with_template_variables(generator: &str) -> MultiDeploymentSpec {
+        MultiDeploymentSpec {
+            template_variables: Some(true),
+            ..spec(generator)
+        }
+    }
+
+    #[test]
+    fn expands_matrix() {
+        let spec = spec_with_template_variables(
+            r#"{matrix: {zone: [a, b], capacity: [spot, on-demand]},
+                nameTemplate: "{{ zone }}-{{ capacity }}",
+                template: {weight: 1, placement: "{{ capacity }}",
+                           podMetadata: {labels: {zone: "{{ zone }}", child: "{{ child }}"}}}}"#,
+        );
+        let (children, generated) = expand_children(&spec).unwrap();
+
+        assert_eq!(
+            generated,
+            ["a-spot", "b-spot", "a-on-demand", "b-on-demand"].map(String::from)
+        );
+        assert_eq!(children.len(), 5);
+        let child = &children["b-spot"];
+        assert_eq!(child.placement.as_deref(), Some("spot"));
+        let labels = child
+            .pod_metadata
+            .as_ref()
+            .unwrap()
+            .labels
+            .as_ref()
+            .unwrap();
+        assert_eq!(labels["zone"], "b");
+        // rendered later with the Deployment
+        assert_eq!(labels["child"], "{{ child }}");
+        let labels = child.deployment_metadata.as_ref().unwrap().labels.as_ref();
+        assert_eq!(labels.unwrap()[GENERATED_LABEL], "true");
+        assert!(children["base"].deployment_metadata.is_none());
+    }
+
+    #[test]
+    fn expands_list() {
+        let spec = spec(
+            r#"{list: [{zone: a}, {zone: b}], nameTemplate: "zone-{{ zone }}", template: {weight: 2}}"#,
+        );
+        let (children, generated) = expand_children(&spec).unwrap();
+
+        assert_eq!(generated, ["zone-a", "zone-b"].map(String::from));
+        assert_eq!(children["zone-b"].weight, Some(2));
+    }
+
+    #[test]
+    fn rejects_invalid_generators() {
+        for generator in [
+            r#"{list: [{zone: a}], nameTemplate: "base", template: {}}"#,
+            r#"{list: [{zone: a}, {zone: a}], nameTemplate: "{{ zone }}", template: {}}"#,
+            r#"{list: [{zone: A}], nameTemplate: "{{ zone }}", template: {}}"#,
+            r#"{list: [{zone: a}], nameTemplate: "{{ region }}", template: {}}"#,
+            r#"{list: [], matrix: {}, nameTemplate: "x", template: {}}"#,
+            // leftover variables are only rendered in the pod template, with templateVariables
+            r#"{list: [{zone: a}], nameTemplate: "{{ zone }}", template: {placement: "{{ zoen }}"}}"#,
+            r#"{list: [{zone: a}], nameTemplate: "{{ zone }}",
+                template: {podMetadata: {labels: {child: "{{ child }}"}}}}"#,
+        ] {
+            assert!(
+                matches!(
+                    expand_children(&spec(generator)),
+                    Err(Error::ValidationError(_))
+                ),
+                "{}",
+                generator
+            );
+        }
+        let generator = r#"{list: [{zone: a}], nameTemplate: "{{ zone }}",
+            template: {deploymentMetadata: {labels: {child: "{{ child }}"}}}}"#;
+        assert!(matches!(
+            expand_children(&spec_with_template_variables(generator)),
+            Err(Error::ValidationError(_))
+        ));
+    }
+}
@@ -1,4 +1,9 @@
 pub mod controller;
 pub mod crd;
+pub mod diff;
+pub mod generator;
+pub mod merge;
+pub mod render;
+pub mod template;
 pub mod types;
 pub mod utils;
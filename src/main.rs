@@ -1,16 +1,35 @@
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
 
 use futures_util::StreamExt;
-use k8s_openapi::api::apps::v1::Deployment;
-use kube::{Client, runtime::Controller};
+use k8s_openapi::api::{
+    apps::v1::Deployment,
+    core::v1::{Node, Pod},
+};
+use kube::{
+    Client,
+    runtime::{
+        Controller, WatchStreamExt,
+        events::{Recorder, Reporter},
+        reflector, watcher,
+    },
+};
 use tracing::info;
 
 use multi_deployment_controller::{
-    controller::{error_policy, reconcile},
-    crd::MultiDeployment,
+    controller::{
+        LABEL_SELECTOR_KEY, error_policy, multi_deployments_for_node,
+        multi_deployments_for_placement_profile, multi_deployments_for_pod, reconcile,
+    },
+    crd::{MultiDeployment, PlacementProfile},
     types::{Context, Error},
 };
 
+const WATCH_NODES_ENV: &str = "MULTI_DEPLOYMENT_WATCH_NODES";
+const WATCH_PLACEMENT_PROFILES_ENV: &str = "MULTI_DEPLOYMENT_WATCH_PLACEMENT_PROFILES";
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     tracing_subscriber::fmt::init();
@@ -18,16 +37,65 @@ async fn main() -> Result<(), Error> {
     let client = Client::try_default().await?;
 
     let multi_deployments = kube::Api::<MultiDeployment>::default_namespaced(client.clone());
-    let deployments = kube::Api::<Deployment>::default_namespaced(client);
+    let deployments = kube::Api::<Deployment>::default_namespaced(client.clone());
+    let pods = kube::Api::<Pod>::default_namespaced(client.clone());
+    // watching nodes needs cluster-wide permissions, so it is opt-in
+    let watch_nodes = std::env::var(WATCH_NODES_ENV).is_ok_and(|v| v == "true" || v == "1");
+    let nodes = watch_nodes.then(|| {
+        let (reader, writer) = reflector::store();
+        let stream = watcher(kube::Api::<Node>::all(client.clone()), Default::default())
+            .default_backoff()
+            .reflect(writer)
+            .applied_objects();
+        (reader, stream)
+    });
+    // placement profiles are only read when children refer to them, watching
+    // them for changes is opt-in like nodes
+    let placement_profiles = kube::Api::<PlacementProfile>::all(client.clone());
+    let watch_placement_profiles =
+        std::env::var(WATCH_PLACEMENT_PROFILES_ENV).is_ok_and(|v| v == "true" || v == "1");
     let ctx = Context {
         multi_deployments: multi_deployments.clone(),
         deployments: deployments.clone(),
+        pods: pods.clone(),
+        placement_profiles: placement_profiles.clone(),
+        nodes: nodes.as_ref().map(|(reader, _)| reader.clone()),
+        recorder: Recorder::new(
+            client,
+            Reporter {
+                controller: "multi-deployment-controller".to_string(),
+                instance: std::env::var("HOSTNAME").ok(),
+            },
+        ),
     };
     let context = Arc::new(ctx);
 
     info!("Starting MultiDeployment controller");
-    Controller::new(multi_deployments, Default::default())
-        .owns(deployments, Default::default())
+    let controller = Controller::new(multi_deployments, Default::default());
+    let store = controller.store();
+    let mut controller = controller.owns(deployments, Default::default()).watches(
+        pods,
+        watcher::Config::default().labels(LABEL_SELECTOR_KEY),
+        {
+            let store = store.clone();
+            move |pod| multi_deployments_for_pod(&store, &pod)
+        },
+    );
+    if watch_placement_profiles {
+        info!("Watching placement profiles");
+        let store = store.clone();
+        controller = controller.watches(placement_profiles, Default::default(), move |profile| {
+            multi_deployments_for_placement_profile(&store, &profile)
+        });
+    }
+    if let Some((_, nodes)) = nodes {
+        info!("Watching nodes for termination taints");
+        let tainted_nodes = Mutex::new(BTreeMap::new());
+        controller = controller.watches_stream(nodes, move |node| {
+            multi_deployments_for_node(&store, &tainted_nodes, &node)
+        });
+    }
+    controller
         .run(reconcile, error_policy, context)
         .for_each(|_res| async move {})
         .await;
@@ -0,0 +1,115 @@
+use serde_json::Value;
+
+/// Merge key of list fields that are merged item by item, following the
+/// patch strategies of the Kubernetes API. Other lists are replaced as a whole.
+fn merge_key(field: &str) -> Option<&'static str> {
+    match field {
+        "containers"
+        | "initContainers"
+        | "ephemeralContainers"
+        | "volumes"
+        | "env"
+        | "imagePullSecrets"
+        | "resourceClaims" => Some("name"),
+        "volumeMounts" => Some("mountPath"),
+        "volumeDevices" => Some("devicePath"),
+        "ports" => Some("containerPort"),
+        "hostAliases" => Some("ip"),
+        "topologySpreadConstraints" => Some("topologyKey"),
+        _ => None,
+    }
+}
+
+/// Strategic merge of `patch` onto `target`:
+/// - objects are merged recursively, `null` removes a field
+/// - lists with a merge key (e.g. containers by name) are merged item by item,
+///   items not found in `target` are appended
+/// - everything else is replaced
+pub fn strategic_merge(target: &mut Value, patch: &Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (field, value) in patch {
+                if value.is_null() {
+                    target.remove(field);
+                    continue;
+                }
+                match (target.get_mut(field), merge_key(field), value) {
+                    (Some(Value::Array(items)), Some(key), Value::Array(patch_items)) => {
+                        merge_list(items, patch_items, key);
+                    }
+                    (Some(existing), _, _) => strategic_merge(existing, value),
+                    (None, _, _) => {
+                        target.insert(field.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, patch) => *target = patch.clone(),
+    }
+}
+
+fn merge_list(items: &mut Vec<Value>, patch_items: &[Value], key: &str) {
+    for patch_item in patch_items {
+        let existing = patch_item
+            .get(key)
+            .and_then(|k| items.iter_mut().find(|item| item.get(key) == Some(k)));
+        match existing {
+            Some(item) => strategic_merge(item, patch_item),
+            None => items.push(patch_item.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn merges_lists_by_key() {
+        let mut target = json!({
+            "containers": [
+                {"name": "app", "image": "app:1", "env": [{"name": "A", "value": "1"}]},
+                {"name": "sidecar", "image": "sidecar:1"},
+            ],
+            "tolerations": [{"key": "a", "operator": "Exists"}],
+        });
+        let patch = json!({
+            "containers": [
+                {"name": "app", "image": "app:2", "env": [{"name": "B", "value": "2"}]},
+                {"name": "extra", "image": "extra:1"},
+            ],
+            "tolerations": [{"key": "b", "operator": "Exists"}],
+        });
+        strategic_merge(&mut target, &patch);
+
+        assert_eq!(
+            target,
+            json!({
+                "containers": [
+                    {
+                        "name": "app",
+                        "image": "app:2",
+                        "env": [{"name": "A", "value": "1"}, {"name": "B", "value": "2"}],
+                    },
+                    {"name": "sidecar", "image": "sidecar:1"},
+                    {"name": "extra", "image": "extra:1"},
+                ],
+                "tolerations": [{"key": "b", "operator": "Exists"}],
+            })
+        );
+    }
+
+    #[test]
+    fn merges_objects() {
+        let mut target = json!({"nodeSelector": {"a": "1", "b": "2"}, "priorityClassName": "x"});
+        let patch = json!({"nodeSelector": {"b": "3", "c": "4"}, "priorityClassName": null});
+        strategic_merge(&mut target, &patch);
+
+        assert_eq!(
+            target,
+            json!({"nodeSelector": {"a": "1", "b": "3", "c": "4"}})
+        );
+    }
+}
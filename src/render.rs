@@ -0,0 +1,1361 @@
+//! Rendering of a MultiDeployment into the allocation of its children and
+//! their Deployments, without side effects.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use k8s_openapi::{
+    api::{
+        apps::v1::{Deployment, DeploymentSpec},
+        core::v1::PodTemplateSpec,
+    },
+    apimachinery::pkg::util::intstr::IntOrString,
+    chrono::{DateTime, SecondsFormat, Utc},
+};
+use kube::{Resource, ResourceExt, api::ObjectMeta};
+
+use crate::{
+    crd::{
+        AllocationStrategy, ChildDeployment, MultiDeployment, MultiDeploymentStatus,
+        PlacementProfileSpec, PropagationRule, RoundingMode, WeightBasis,
+    },
+    generator, merge, template,
+    types::Error,
+    utils,
+};
+
+pub const CONTROLLER_NAME: &str = "multi-deployment-controller";
+pub const LABEL_SELECTOR_KEY: &str = "multi-deployment.skystar.dev/managed-by";
+/// Pod template annotation `kubectl rollout restart` sets.
+pub const RESTARTED_AT_ANNOTATION: &str = "kubectl.kubernetes.io/restartedAt";
+/// Hash of the rendered pod template, set on Deployments with `rollout`.
+pub const TEMPLATE_HASH_ANNOTATION: &str = "multi-deployment.skystar.dev/template-hash";
+/// Label of Deployments of generated children, which are deleted once they're
+/// no longer generated.
+pub const GENERATED_LABEL: &str = "multi-deployment.skystar.dev/generated";
+
+const DEFAULT_WEIGHT_RESOURCE: &str = "cpu";
+
+/// Inputs of rendering besides the MultiDeployment itself.
+#[derive(Clone, Debug, Default)]
+pub struct RenderOptions {
+    /// Status of the previous reconciliation, for `rounding: Stable` and
+    /// scale priorities. Usually the status of the MultiDeployment.
+    pub previous: Option<MultiDeploymentStatus>,
+    /// Cluster-wide `PlacementProfile` resources children refer to, by name.
+    pub placement_profiles: BTreeMap<String, PlacementProfileSpec>,
+    /// Time children with `expiresAt` or `ttl` expire at. No child expires
+    /// without it.
+    pub now: Option<DateTime<Utc>>,
+    /// Creation times of the existing child Deployments by child name, which
+    /// `ttl` counts from.
+    pub deployments_created: BTreeMap<String, DateTime<Utc>>,
+}
+
+/// When a child with `expiresAt` or `ttl` expires.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Expiry {
+    pub expires_at: DateTime<Utc>,
+    pub expired: bool,
+}
+
+/// Target allocation of the children, in the order of `children`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Allocation {
+    pub children: Vec<String>,
+    pub minimums: Vec<i64>,
+    pub maximums: Vec<Option<i64>>,
+    pub replicas: Vec<i64>,
+    /// Hash of what the allocation depends on besides `replicas`. Scale
+    /// priorities only start from the previous allocation while it's unchanged.
+    pub inputs_hash: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Rendered {
+    pub allocation: Allocation,
+    /// Deployments of the children whose patches could be applied.
+    pub deployments: Vec<Deployment>,
+    /// Why `patches` of a child could not be applied, by child name.
+    pub patch_errors: BTreeMap<String, String>,
+    /// Children added by `generators`.
+    pub generated_children: Vec<String>,
+    /// Children that expired. They have no Deployment, the controller
+    /// deletes it.
+    pub expired_children: Vec<String>,
+}
+
+/// Renders the Deployments of all children of `source`, with the target
+/// allocation as replicas.
+pub fn render(source: &MultiDeployment, options: &RenderOptions) -> Result<Rendered, Error> {
+    let (mut source, generated_children) = expand(source)?;
+    let expiries = expire(&mut source, options);
+    let allocation = allocate(&source, options)?;
+
+    let mut deployments = Vec::with_capacity(allocation.children.len());
+    let mut patch_errors = BTreeMap::new();
+    let mut expired_children = Vec::new();
+    for (child_name, &replicas) in allocation.children.iter().zip(&allocation.replicas) {
+        if expiries.get(child_name).is_some_and(|e| e.expired) {
+            expired_children.push(child_name.clone());
+            continue;
+        }
+        match render_deployment(
+            &source,
+            child_name,
+            Some(replicas as i32),
+            replicas,
+            options,
+        ) {
+            Ok(deployment) => deployments.push(deployment),
+            Err(Error::PatchError(message)) => {
+                patch_errors.insert(child_name.clone(), message);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(Rendered {
+        allocation,
+        deployments,
+        patch_errors,
+        generated_children,
+        expired_children,
+    })
+}
+
+/// `source` with the children of its generators added, and the names of
+/// the generated children.
+pub fn expand(source: &MultiDeployment) -> Result<(MultiDeployment, Vec<String>), Error> {
+    let (children, generated_children) = generator::expand_children(&source.spec)?;
+    let mut expanded = source.clone();
+    expanded.spec.children = children;
+    expanded.spec.generators = None;
+    Ok((expanded, generated_children))
+}
+
+/// Expiry of the children with `expiresAt` or `ttl`, by child name. Expired
+/// children become children without weight and replicas. A `ttl` counts from
+/// the creation of the child's Deployment; once that is deleted, the expiry
+/// recorded in the previous status applies.
+pub fn expire(source: &mut MultiDeployment, options: &RenderOptions) -> BTreeMap<String, Expiry> {
+    let mut expiries = BTreeMap::new();
+    let Some(now) = options.now else {
+        return expiries;
+    };
+    let previous_children = options.previous.as_ref().and_then(|s| s.children.as_ref());
+    for (child_name, child) in source.spec.children.iter_mut() {
+        let expires_at = match (&child.expires_at, &child.ttl) {
+            (Some(expires_at), _) => expires_at.0,
+            // invalid ttls are reported by `allocate`
+            (None, Some(ttl)) => {
+                let Some(ttl) = utils::parse_duration(ttl) else {
+                    continue;
+                };
+                let recorded = previous_children
+                    .and_then(|c| c.get(child_name))
+                    .and_then(|c| c.expires_at.as_ref());
+                match (options.deployments_created.get(child_name), recorded) {
+                    (Some(created), _) => *created + ttl,
+                    (None, Some(expires_at)) => expires_at.0,
+                    (None, None) => now + ttl,
+                }
+            }
+            (None, None) => continue,
+        };
+        let expired = now >= expires_at;
+        if expired {
+            child.weight = Some(0);
+            child.min_replicas = None;
+            child.replicas = None;
+            child.max_replicas = Some(0);
+        }
+        expiries.insert(
+            child_name.clone(),
+            Expiry {
+                expires_at,
+                expired,
+            },
+        );
+    }
+    expiries
+}
+
+/// Names of the placement profiles children refer to that aren't defined on
+/// the MultiDeployment, i.e. `PlacementProfile` resources.
+pub fn cluster_placement_profiles(source: &MultiDeployment) -> BTreeSet<String> {
+    let inline = source.spec.placement_profiles.as_ref();
+    source
+        .spec
+        .children
+        .values()
+        .filter_map(|child| child.placement.clone())
+        .filter(|placement| !inline.is_some_and(|p| p.contains_key(placement)))
+        .collect()
+}
+
+/// Children in the order `rollout` updates them: those listed in `order`,
+/// then the others by name.
+pub fn rollout_order(source: &MultiDeployment) -> Result<Vec<String>, Error> {
+    let listed = source
+        .spec
+        .rollout
+        .as_ref()
+        .and_then(|r| r.order.clone())
+        .unwrap_or_default();
+    for (i, child_name) in listed.iter().enumerate() {
+        if !source.spec.children.contains_key(child_name) || listed[..i].contains(child_name) {
+            return Err(Error::ValidationError(format!(
+                "Rollout order must list existing children once, found {}",
+                child_name
+            )));
+        }
+    }
+    let others: Vec<String> = source
+        .spec
+        .children
+        .keys()
+        .filter(|child_name| !listed.contains(child_name))
+        .cloned()
+        .collect();
+    Ok([listed, others].concat())
+}
+
+/// Children ordered so that every child comes after the children it depends
+/// on. Fails if a dependency doesn't exist or dependencies form a cycle.
+pub fn dependency_order(source: &MultiDeployment) -> Result<Vec<String>, Error> {
+    let children = &source.spec.children;
+    for (child_name, child) in children {
+        if let Some(missing) = child
+            .depends_on
+            .iter()
+            .flatten()
+            .find(|dependency| !children.contains_key(*dependency))
+        {
+            return Err(Error::ValidationError(format!(
+                "Child deployment {} depends on unknown child {}",
+                child_name, missing
+            )));
+        }
+    }
+
+    let mut order: Vec<String> = Vec::with_capacity(children.len());
+    while order.len() < children.len() {
+        let ready: Vec<String> = children
+            .iter()
+            .filter(|(child_name, _)| !order.contains(child_name))
+            .filter(|(_, child)| child.depends_on.iter().flatten().all(|d| order.contains(d)))
+            .map(|(child_name, _)| child_name.clone())
+            .collect();
+        if ready.is_empty() {
+            let cycle: Vec<&str> = children
+                .keys()
+                .filter(|child_name| !order.contains(child_name))
+                .map(String::as_str)
+                .collect();
+            return Err(Error::ValidationError(format!(
+                "Child deployments {} depend on each other",
+                cycle.join(", ")
+            )));
+        }
+        order.extend(ready);
+    }
+    Ok(order)
+}
+
+/// Hash of a pod template, to tell whether a Deployment runs the rendered
+/// template.
+pub fn template_hash(template: &PodTemplateSpec) -> Result<String, Error> {
+    Ok(fnv1a(&serde_json::to_vec(template)?))
+}
+
+/// FNV-1a hash, stable across releases unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf29ce484222325_u64, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Name of the Deployment of a child.
+pub fn deployment_name(source_name: &str, child_name: &str) -> String {
+    format!("{}-{}", source_name, child_name)
+}
+
+/// Validates `source` and calculates the target allocation of its children.
+/// Generators must have been expanded with [`expand`].
+pub fn allocate(source: &MultiDeployment, options: &RenderOptions) -> Result<Allocation, Error> {
+    // check that at least one child deployment is defined
+    if source.spec.children.is_empty() {
+        return Err(Error::ValidationError(
+            "At least one child deployment must be defined".to_string(),
+        ));
+    }
+    rollout_order(source)?;
+    dependency_order(source)?;
+    for (child_name, child) in &source.spec.children {
+        if child.expires_at.is_some() && child.ttl.is_some() {
+            return Err(Error::ValidationError(format!(
+                "Child deployment {} can have either expiresAt or ttl",
+                child_name
+            )));
+        }
+        if let Some(ttl) = &child.ttl
+            && utils::parse_duration(ttl).is_none()
+        {
+            return Err(Error::ValidationError(format!(
+                "Invalid ttl {} of child deployment {}",
+                ttl, child_name
+            )));
+        }
+    }
+
+    // validate that no child deployment has negative values
+    if source
+        .spec
+        .children
+        .values()
+        .map(|c| c.weight.unwrap_or(0))
+        .any(|w| w < 0)
+    {
+        return Err(Error::ValidationError(
+            "Child deployment weights cannot be negative".to_string(),
+        ));
+    }
+    for (child_name, child) in &source.spec.children {
+        if let Some(fallback) = &child.fallback
+            && (fallback.child == *child_name
+                || !source.spec.children.contains_key(&fallback.child))
+        {
+            return Err(Error::ValidationError(format!(
+                "Fallback of child deployment {} must be another child deployment",
+                child_name
+            )));
+        }
+    }
+
+    let total_replicas = source.spec.replicas.unwrap_or(0);
+    if total_replicas < 0 {
+        return Err(Error::ValidationError(
+            "Total replicas cannot be negative".to_string(),
+        ));
+    }
+
+    // resolve min_replicas, percentages are relative to total replicas and
+    // rounded down here, their remainders are rounded up below
+    let mut minimums: Vec<i64> = Vec::with_capacity(source.spec.children.len());
+    let mut remainders: Vec<(i64, usize)> = Vec::new();
+    for (i, child) in source.spec.children.values().enumerate() {
+        let minimum = match &child.min_replicas {
+            Some(value @ IntOrString::String(_)) => {
+                let scaled =
+                    utils::resolve_int_or_percent(value, i64::from(total_replicas) * 100, false)?;
+                if scaled % 100 != 0 {
+                    remainders.push((scaled % 100, i));
+                }
+                scaled.div_euclid(100)
+            }
+            Some(value) => utils::resolve_int_or_percent(value, total_replicas.into(), true)?,
+            None => 0,
+        };
+        if minimum < 0 {
+            return Err(Error::ValidationError(
+                "Child deployment min_replicas cannot be negative".to_string(),
+            ));
+        }
+        if matches!(child.min_replicas, Some(IntOrString::Int(_)))
+            && child
+                .max_replicas
+                .is_some_and(|max| i64::from(max) < minimum)
+        {
+            return Err(Error::ValidationError(
+                "Child deployment max_replicas cannot be less than min_replicas".to_string(),
+            ));
+        }
+        minimums.push(minimum);
+    }
+    if source
+        .spec
+        .children
+        .values()
+        .any(|c| c.replicas.is_some_and(|r| r < 0))
+    {
+        return Err(Error::ValidationError(
+            "Child deployment replicas cannot be negative".to_string(),
+        ));
+    }
+
+    // children with fixed replicas are pinned outside of the weighted pool
+    let mut maximums: Vec<Option<i64>> = Vec::with_capacity(source.spec.children.len());
+    let mut weights: Vec<f64> = Vec::with_capacity(source.spec.children.len());
+    for (i, child) in source.spec.children.values().enumerate() {
+        match child.replicas {
+            Some(replicas) => {
+                minimums[i] = replicas.into();
+                maximums.push(Some(replicas.into()));
+                weights.push(0.0);
+            }
+            None => {
+                maximums.push(child.max_replicas.map(Into::into));
+                weights.push(child.weight.unwrap_or(0).into());
+            }
+        }
+    }
+
+    // split the capacity of a resource by weight, instead of the replica count
+    if source.spec.weight_basis.unwrap_or_default() == WeightBasis::Resources {
+        let resource = source
+            .spec
+            .weight_resource
+            .as_deref()
+            .unwrap_or(DEFAULT_WEIGHT_RESOURCE);
+        let mut per_replica = Vec::with_capacity(weights.len());
+        for (i, (child_name, child)) in source.spec.children.iter().enumerate() {
+            if weights[i] == 0.0 {
+                per_replica.push(1.0);
+                continue;
+            }
+            // failing patches are reported when rendering the Deployments, and the
+            // allocation isn't known yet
+            let deployment = create_deployment(source, child_name, None, 0, options)?;
+            let deployment = patch_deployment(deployment.clone(), child).unwrap_or(deployment);
+            let request = deployment
+                .spec
+                .and_then(|s| s.template.spec)
+                .and_then(|pod_spec| utils::pod_resource_request(&pod_spec, resource))
+                .ok_or_else(|| {
+                    Error::ValidationError(format!(
+                        "Child deployment {} has no {} request, required by weightBasis Resources",
+                        child_name, resource
+                    ))
+                })?;
+            per_replica.push(request);
+        }
+        weights = utils::capacity_weights(&weights, &per_replica)?;
+    }
+
+    // total_replicas == 0 is exception, meaning "(temporarily) disabled"
+    if total_replicas == 0 {
+        minimums.fill(0);
+    }
+
+    // percentages are capped by max_replicas
+    for (child, (minimum, maximum)) in source
+        .spec
+        .children
+        .values()
+        .zip(minimums.iter_mut().zip(&maximums))
+    {
+        if matches!(child.min_replicas, Some(IntOrString::String(_)))
+            && let Some(maximum) = maximum
+        {
+            *minimum = (*minimum).min(*maximum);
+        }
+    }
+
+    // then round up the largest remainders while the minimums fit into total
+    // replicas
+    let mut spare = i64::from(total_replicas) - minimums.iter().sum::<i64>();
+    remainders.sort_by_key(|&(remainder, _)| std::cmp::Reverse(remainder));
+    for (_, i) in remainders {
+        if spare <= 0 {
+            break;
+        }
+        if maximums[i].is_none_or(|max| minimums[i] < max) {
+            minimums[i] += 1;
+            spare -= 1;
+        }
+    }
+
+    // validate that total min_replicas does not exceed total replicas
+    let total_min_replicas: i64 = minimums.iter().sum();
+    if total_min_replicas > total_replicas.into() {
+        return Err(Error::ValidationError(
+            "Sum of min_replicas and fixed replicas of child deployments exceeds total replicas"
+                .to_string(),
+        ));
+    }
+
+    let total_weight: f64 = weights.iter().sum();
+    let pinned_replicas: i64 = source
+        .spec
+        .children
+        .values()
+        .filter_map(|c| c.replicas.map(i64::from))
+        .sum();
+
+    let strategy = source.spec.allocation_strategy.unwrap_or_default();
+    if total_weight == 0.0
+        && i64::from(total_replicas) > pinned_replicas
+        && strategy != AllocationStrategy::PriorityFill
+    {
+        // total_replicas is non-zero, but total_weight is zero
+        // this can be regarded as even distribution, but to avoid confusion, we raise an error
+        return Err(Error::ValidationError(
+            "Total weight of child deployments cannot be zero when total replicas is non-zero"
+                .to_string(),
+        ));
+    }
+
+    // do allocation
+    let previous_children = options
+        .previous
+        .as_ref()
+        .and_then(|s| s.children.clone())
+        .unwrap_or_default();
+
+    // stable rounding starts from the previous allocation
+    let stable_previous: Option<Vec<i64>> =
+        (source.spec.rounding.unwrap_or_default() == RoundingMode::Stable).then(|| {
+            source
+                .spec
+                .children
+                .keys()
+                .map(|child_name| {
+                    previous_children
+                        .get(child_name)
+                        .map_or(0, |c| c.target_replicas.into())
+                })
+                .collect()
+        });
+    let calculated_replicas = match strategy {
+        AllocationStrategy::Weighted => match &stable_previous {
+            Some(previous) => utils::allocate_weighted_stable(
+                total_replicas.into(),
+                &minimums,
+                &maximums,
+                &weights,
+                previous,
+            )?,
+            None => utils::allocate_weighted_with_bounds(
+                total_replicas.into(),
+                &minimums,
+                &maximums,
+                &weights,
+            )?,
+        },
+        AllocationStrategy::FixedThenWeighted => utils::allocate_fixed_then_weighted(
+            total_replicas.into(),
+            &minimums,
+            &maximums,
+            &weights,
+            stable_previous.as_deref(),
+        )?,
+        AllocationStrategy::PriorityFill => {
+            let priorities: Vec<i64> = source
+                .spec
+                .children
+                .values()
+                .map(|c| c.priority.unwrap_or(0).into())
+                .collect();
+            utils::allocate_priority_fill(total_replicas.into(), &minimums, &maximums, &priorities)?
+        }
+    };
+
+    // everything the weighted allocation depends on besides the total
+    let inputs = serde_json::json!({
+        "allocationStrategy": strategy,
+        "rounding": source.spec.rounding.unwrap_or_default(),
+        "weights": weights,
+        "children": source
+            .spec
+            .children
+            .iter()
+            .map(|(child_name, child)| {
+                let bounds = serde_json::json!({
+                    "minReplicas": child.min_replicas,
+                    "maxReplicas": child.max_replicas,
+                    "replicas": child.replicas,
+                });
+                (child_name.clone(), bounds)
+            })
+            .collect::<serde_json::Map<_, _>>(),
+    });
+    let inputs_hash = fnv1a(&serde_json::to_vec(&inputs)?);
+
+    // hand replica count changes to children by scale priority, as long as
+    // the previous allocation was made with the same inputs
+    let has_scale_priorities = source
+        .spec
+        .children
+        .values()
+        .any(|c| c.scale_up_priority.is_some() || c.scale_down_priority.is_some());
+    let same_inputs = options
+        .previous
+        .as_ref()
+        .is_some_and(|s| s.allocation_hash.as_ref() == Some(&inputs_hash));
+    let previous_targets: Option<Vec<i64>> = source
+        .spec
+        .children
+        .keys()
+        .map(|child_name| {
+            previous_children
+                .get(child_name)
+                .filter(|_| same_inputs)
+                .map(|c| c.target_replicas.into())
+        })
+        .collect();
+    let calculated_replicas = match previous_targets {
+        Some(previous_targets)
+            if has_scale_priorities && strategy != AllocationStrategy::PriorityFill =>
+        {
+            let up_priorities: Vec<i64> = source
+                .spec
+                .children
+                .values()
+                .map(|c| c.scale_up_priority.unwrap_or(0).into())
+                .collect();
+            let down_priorities: Vec<i64> = source
+                .spec
+                .children
+                .values()
+                .map(|c| c.scale_down_priority.unwrap_or(0).into())
+                .collect();
+            utils::allocate_marginal(
+                total_replicas.into(),
+                &previous_targets,
+                &calculated_replicas,
+                &minimums,
+                &maximums,
+                &up_priorities,
+                &down_priorities,
+            )?
+        }
+        _ => calculated_replicas,
+    };
+
+    Ok(Allocation {
+        children: source.spec.children.keys().cloned().collect(),
+        minimums,
+        maximums,
+        replicas: calculated_replicas,
+        inputs_hash,
+    })
+}
+
+/// Renders the Deployment of a child, with its patches applied. `replicas`
+/// is set on the Deployment, `allocated_replicas` is the `{{ replicas }}`
+/// template variable. With `rollout`, the hash of the pod template is
+/// recorded in an annotation. Fails with [`Error::PatchError`] if the patches
+/// cannot be applied.
+pub fn render_deployment(
+    source: &MultiDeployment,
+    child_name: &str,
+    replicas: Option<i32>,
+    allocated_replicas: i64,
+    options: &RenderOptions,
+) -> Result<Deployment, Error> {
+    let deployment = create_deployment(source, child_name, replicas, allocated_replicas, options)?;
+    let mut deployment = patch_deployment(deployment, &source.spec.children[child_name])?;
+    if source.spec.rollout.is_some()
+        && let Some(spec) = &deployment.spec
+    {
+        let hash = template_hash(&spec.template)?;
+        deployment
+            .annotations_mut()
+            .insert(TEMPLATE_HASH_ANNOTATION.to_string(), hash);
+    }
+    Ok(deployment)
+}
+
+/// Renders the Deployment of a child, without its patches.
+fn create_deployment(
+    source: &MultiDeployment,
+    child_name: &str,
+    replicas: Option<i32>,
+    allocated_replicas: i64,
+    options: &RenderOptions,
+) -> Result<Deployment, Error> {
+    let source_name = source.name_any();
+    let child_deployment = source.spec.children.get(child_name).ok_or_else(|| {
+        Error::ValidationError(format!("Child deployment {} not found", child_name))
+    })?;
+
+    let managed_by = deployment_name(&source_name, child_name);
+
+    // create unique selector based on source and child names
+    let mut new_selector = source.spec.root_template.selector.clone();
+    let selector_labels = new_selector.match_labels.get_or_insert_with(BTreeMap::new);
+
+    // labels and annotations copied from the MultiDeployment
+    let propagation = source.spec.metadata_propagation.clone().unwrap_or_default();
+    let propagated_labels = propagate(source.labels(), propagation.labels.as_ref());
+    let propagated_annotations = propagate(source.annotations(), propagation.annotations.as_ref());
+    let root_metadata = source.spec.root_template.template.metadata.as_ref();
+    let (mut new_labels, mut new_annotations) = if propagation.pod_template.unwrap_or(false) {
+        (propagated_labels.clone(), propagated_annotations.clone())
+    } else {
+        Default::default()
+    };
+
+    // create new labels based on root template and child labels
+    new_labels.extend(
+        root_metadata
+            .and_then(|m| m.labels.clone())
+            .unwrap_or_default(),
+    );
+    new_annotations.extend(
+        root_metadata
+            .and_then(|m| m.annotations.clone())
+            .unwrap_or_default(),
+    );
+    let pod_metadata = child_deployment.pod_metadata.clone().unwrap_or_default();
+    new_annotations.extend(pod_metadata.annotations.unwrap_or_default());
+    let restarted_at = [&source.spec.restarted_at, &child_deployment.restarted_at]
+        .into_iter()
+        .flatten()
+        .max_by_key(|t| t.0);
+    if let Some(restarted_at) = restarted_at {
+        new_annotations.insert(
+            RESTARTED_AT_ANNOTATION.to_string(),
+            restarted_at.0.to_rfc3339_opts(SecondsFormat::Secs, true),
+        );
+    }
+    for (key, value) in pod_metadata.labels.unwrap_or_default() {
+        // pods must keep matching the selector of the root template
+        if selector_labels.get(&key).is_some_and(|v| *v != value) {
+            return Err(Error::ValidationError(format!(
+                "Pod label {} of child deployment {} conflicts with the selector",
+                key, child_name
+            )));
+        }
+        new_labels.insert(key, value);
+    }
+
+    selector_labels.insert(LABEL_SELECTOR_KEY.to_string(), managed_by.clone());
+    new_labels.insert(LABEL_SELECTOR_KEY.to_string(), managed_by.clone());
+
+    // build child deployment spec
+    let root = &source.spec.root_template;
+    let child_deployment_data = DeploymentSpec {
+        selector: new_selector,
+        template: PodTemplateSpec {
+            metadata: Some(ObjectMeta {
+                labels: Some(new_labels),
+                annotations: (!new_annotations.is_empty()).then_some(new_annotations),
+                ..Default::default()
+            }),
+            spec: child_deployment.pod_spec.clone(),
+        },
+        replicas,
+        min_ready_seconds: child_deployment
+            .min_ready_seconds
+            .or(root.min_ready_seconds),
+        progress_deadline_seconds: child_deployment
+            .progress_deadline_seconds
+            .or(root.progress_deadline_seconds),
+        revision_history_limit: child_deployment
+            .revision_history_limit
+            .or(root.revision_history_limit),
+        ..root.clone()
+    };
+
+    // merge root spec, placement profile and child spec
+    let mut root_spec = serde_json::to_value(root)?;
+    if let Some(placement) = &child_deployment.placement {
+        let profile = source
+            .spec
+            .placement_profiles
+            .as_ref()
+            .and_then(|p| p.get(placement))
+            .or_else(|| options.placement_profiles.get(placement))
+            .ok_or_else(|| {
+                Error::ValidationError(format!("Placement profile {} not found", placement))
+            })?;
+        let profile_spec = serde_json::json!({ "template": { "spec": profile } });
+        merge::strategic_merge(&mut root_spec, &profile_spec);
+    }
+    let child_spec = serde_json::to_value(&child_deployment_data)?;
+
+    merge::strategic_merge(&mut root_spec, &child_spec);
+    let mut root_spec: DeploymentSpec = serde_json::from_value(root_spec)?;
+
+    // a strategy is replaced as a whole, rollingUpdate only fits its own type
+    if child_deployment.strategy.is_some() {
+        root_spec.strategy = child_deployment.strategy.clone();
+    }
+
+    // substitute template variables in the strings of the pod template
+    if source.spec.template_variables == Some(true) {
+        let variables = BTreeMap::from([
+            ("parent", source_name.clone()),
+            ("child", child_name.to_string()),
+            ("namespace", source.namespace().unwrap_or_default()),
+            ("deployment", managed_by.clone()),
+            ("replicas", allocated_replicas.to_string()),
+            ("weight", child_deployment.weight.unwrap_or(0).to_string()),
+        ]);
+        let mut pod_template = serde_json::to_value(&root_spec.template)?;
+        template::render(&mut pod_template, &variables).map_err(|e| {
+            Error::ValidationError(format!("Child deployment {}: {}", child_name, e))
+        })?;
+        root_spec.template = serde_json::from_value(pod_template)?;
+    }
+
+    // Deployment metadata: propagated, then child, then standard labels
+    let deployment_metadata = child_deployment
+        .deployment_metadata
+        .clone()
+        .unwrap_or_default();
+    let mut labels = propagated_labels;
+    labels.extend(deployment_metadata.labels.unwrap_or_default());
+    labels.extend([
+        (
+            "app.kubernetes.io/managed-by".to_string(),
+            CONTROLLER_NAME.to_string(),
+        ),
+        ("app.kubernetes.io/part-of".to_string(), source_name),
+        (
+            "app.kubernetes.io/component".to_string(),
+            child_name.to_string(),
+        ),
+    ]);
+    let mut annotations = propagated_annotations;
+    annotations.extend(deployment_metadata.annotations.unwrap_or_default());
+
+    let deployment = Deployment {
+        metadata: ObjectMeta {
+            name: Some(managed_by),
+            labels: Some(labels),
+            annotations: (!annotations.is_empty()).then_some(annotations),
+            // only objects read from the cluster have a uid to refer to
+            owner_references: source.controller_owner_ref(&()).map(|oref| vec![oref]),
+            ..Default::default()
+        },
+        spec: Some(root_spec),
+
+        ..Default::default()
+    };
+
+    Ok(deployment)
+}
+
+/// Labels or annotations of the MultiDeployment copied to its children.
+fn propagate(
+    metadata: &BTreeMap<String, String>,
+    rule: Option<&PropagationRule>,
+) -> BTreeMap<String, String> {
+    let Some(rule) = rule else {
+        return BTreeMap::new();
+    };
+    let matches = |prefixes: &Option<Vec<String>>, key: &str| {
+        prefixes
+            .as_ref()
+            .is_some_and(|p| p.iter().any(|prefix| key.starts_with(prefix.as_str())))
+    };
+    metadata
+        .iter()
+        .filter(|(key, _)| rule.allow.is_none() || matches(&rule.allow, key))
+        .filter(|(key, _)| !matches(&rule.deny, key) && !key.starts_with("kubectl.kubernetes.io/"))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Applies the JSON patches of a child to its rendered Deployment.
+fn patch_deployment(
+    deployment: Deployment,
+    child_deployment: &ChildDeployment,
+) -> Result<Deployment, Error> {
+    let Some(operations) = &child_deployment.patches else {
+        return Ok(deployment);
+    };
+    let patch_error = |e: &dyn std::fmt::Display| Error::PatchError(e.to_string());
+
+    let patch: json_patch::Patch = serde_json::to_value(operations)
+        .and_then(serde_json::from_value)
+        .map_err(|e| patch_error(&e))?;
+    let mut deployment = serde_json::to_value(&deployment)?;
+    json_patch::patch(&mut deployment, &patch).map_err(|e| patch_error(&e))?;
+    serde_json::from_value(deployment).map_err(|e| patch_error(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+
+    use crate::crd::{ChildMetadata, JsonPatchOp, JsonPatchOperation, PlacementProfile};
+
+    /// Renders the children of `source` with the given replicas and compares
+    /// them with the Deployments in `expected`. Documents following the
+    /// MultiDeployment in `source` are cluster-wide placement profiles.
+    fn assert_rendered(source: &str, expected: &str, replicas: &[(&str, i32)]) {
+        let mut documents = serde_yaml::Deserializer::from_str(source);
+        let source: MultiDeployment =
+            serde::Deserialize::deserialize(documents.next().unwrap()).unwrap();
+        let options = RenderOptions {
+            placement_profiles: documents
+                .map(|document| {
+                    let profile: PlacementProfile =
+                        serde::Deserialize::deserialize(document).unwrap();
+                    (profile.name_any(), profile.spec)
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let expected: Vec<serde_json::Value> = serde_yaml::Deserializer::from_str(expected)
+            .map(|document| serde::Deserialize::deserialize(document).unwrap())
+            .collect();
+
+        let rendered: Vec<serde_json::Value> = replicas
+            .iter()
+            .map(|(child, replicas)| {
+                let deployment = render_deployment(
+                    &source,
+                    child,
+                    Some(*replicas),
+                    (*replicas).into(),
+                    &options,
+                )
+                .unwrap();
+                serde_json::to_value(deployment).unwrap()
+            })
+            .collect();
+
+        for (rendered, expected) in rendered.iter().zip(&expected) {
+            assert_eq!(
+                rendered,
+                expected,
+                "rendered:\n{}",
+                serde_yaml::to_string(rendered).unwrap()
+            );
+        }
+        assert_eq!(rendered.len(), expected.len());
+    }
+
+    /// The MultiDeployment of the strategic-merge golden test, for tests that
+    /// change it.
+    fn strategic_merge_fixture() -> MultiDeployment {
+        serde_yaml::from_str(include_str!(
+            "../testdata/strategic-merge/multideployment.yaml"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn renders_allocation_and_deployments() {
+        let mut source = strategic_merge_fixture();
+        // e.g. a manifest in CI, which has no uid yet
+        source.metadata.uid = None;
+        source.spec.generators = Some(vec![
+            serde_yaml::from_str(
+                r#"{list: [{zone: a}], nameTemplate: "zone-{{ zone }}", template: {weight: 3}}"#,
+            )
+            .unwrap(),
+        ]);
+        source.spec.replicas = Some(10);
+
+        let rendered = render(&source, &RenderOptions::default()).unwrap();
+
+        assert_eq!(
+            rendered.allocation.children,
+            ["canary", "spot", "stable", "zone-a"].map(String::from)
+        );
+        assert_eq!(rendered.allocation.replicas, [1, 3, 3, 3]);
+        assert_eq!(rendered.generated_children, ["zone-a"].map(String::from));
+        assert!(rendered.patch_errors.is_empty());
+        let replicas: Vec<Option<i32>> = rendered
+            .deployments
+            .iter()
+            .map(|d| d.spec.as_ref().unwrap().replicas)
+            .collect();
+        assert_eq!(replicas, [Some(1), Some(3), Some(3), Some(3)]);
+        assert!(
+            rendered
+                .deployments
+                .iter()
+                .all(|d| d.metadata.owner_references.is_none())
+        );
+    }
+
+    /// A MultiDeployment with `replicas` and the `children` given as YAML.
+    fn multi_deployment(replicas: i32, children: &str) -> MultiDeployment {
+        serde_yaml::from_str(&format!(
+            "{{apiVersion: skystar.dev/v1, kind: MultiDeployment, metadata: {{name: web}}, \
+             spec: {{name: web, replicas: {}, rootTemplate: {{selector: {{}}, template: {{}}}}, \
+             children: {}}}}}",
+            replicas, children
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn percentage_minimums_fit_total() {
+        let children =
+            r#"{od: {weight: 7, minReplicas: "70%"}, spot: {weight: 3, minReplicas: "30%"}}"#;
+        for total in 1..=25 {
+            let allocation = allocate(&multi_deployment(total, children), &Default::default())
+                .unwrap_or_else(|e| panic!("{} replicas: {}", total, e));
+            assert!(allocation.minimums.iter().sum::<i64>() <= total.into());
+            assert_eq!(allocation.replicas.iter().sum::<i64>(), i64::from(total));
+        }
+        // 3.5 and 1.5, ties are rounded up in the order of the children
+        let allocation = allocate(&multi_deployment(5, children), &Default::default()).unwrap();
+        assert_eq!(allocation.minimums, [4, 1]);
+        let allocation = allocate(&multi_deployment(7, children), &Default::default()).unwrap();
+        assert_eq!(allocation.minimums, [5, 2]);
+
+        // percentages are capped by maxReplicas instead of rejected
+        let children =
+            r#"{od: {weight: 1}, spot: {weight: 1, minReplicas: "50%", maxReplicas: 3}}"#;
+        let allocation = allocate(&multi_deployment(10, children), &Default::default()).unwrap();
+        assert_eq!(allocation.minimums, [0, 3]);
+        assert_eq!(allocation.replicas, [7, 3]);
+        let children = r#"{od: {weight: 1}, spot: {weight: 1, minReplicas: 5, maxReplicas: 3}}"#;
+        assert!(matches!(
+            allocate(&multi_deployment(10, children), &Default::default()),
+            Err(Error::ValidationError(_))
+        ));
+    }
+
+    #[test]
+    fn pinned_replicas() {
+        let children = r#"{batch: {replicas: 2, weight: 5}, web: {weight: 1, minReplicas: "50%"}}"#;
+        let allocation = allocate(&multi_deployment(5, children), &Default::default()).unwrap();
+        assert_eq!(allocation.minimums, [2, 3]);
+        assert_eq!(allocation.maximums, [Some(2), None]);
+        assert_eq!(allocation.replicas, [2, 3]);
+
+        // no weight is needed when the pinned replicas are all replicas
+        let children = "{batch: {replicas: 3}, web: {weight: 0}}";
+        let allocation = allocate(&multi_deployment(3, children), &Default::default()).unwrap();
+        assert_eq!(allocation.replicas, [3, 0]);
+        for total in [2, 4] {
+            assert!(matches!(
+                allocate(&multi_deployment(total, children), &Default::default()),
+                Err(Error::ValidationError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn strategic_merge_golden() {
+        assert_rendered(
+            include_str!("../testdata/strategic-merge/multideployment.yaml"),
+            include_str!("../testdata/strategic-merge/deployments.yaml"),
+            &[("canary", 1), ("spot", 2), ("stable", 1)],
+        );
+    }
+
+    #[test]
+    fn json_patch_golden() {
+        assert_rendered(
+            include_str!("../testdata/json-patch/multideployment.yaml"),
+            include_str!("../testdata/json-patch/deployments.yaml"),
+            &[("debug", 1)],
+        );
+    }
+
+    #[test]
+    fn child_overrides_golden() {
+        assert_rendered(
+            include_str!("../testdata/child-overrides/multideployment.yaml"),
+            include_str!("../testdata/child-overrides/deployments.yaml"),
+            &[("batch", 2)],
+        );
+    }
+
+    #[test]
+    fn metadata_propagation_golden() {
+        assert_rendered(
+            include_str!("../testdata/metadata-propagation/multideployment.yaml"),
+            include_str!("../testdata/metadata-propagation/deployments.yaml"),
+            &[("main", 1)],
+        );
+    }
+
+    #[test]
+    fn template_variables_golden() {
+        assert_rendered(
+            include_str!("../testdata/template-variables/multideployment.yaml"),
+            include_str!("../testdata/template-variables/deployments.yaml"),
+            &[("canary", 3)],
+        );
+    }
+
+    #[test]
+    fn unknown_template_variable() {
+        let mut source: MultiDeployment = serde_yaml::from_str(include_str!(
+            "../testdata/template-variables/multideployment.yaml"
+        ))
+        .unwrap();
+        let child = source.spec.children.get_mut("canary").unwrap();
+        child.pod_metadata = Some(ChildMetadata {
+            labels: None,
+            annotations: Some(BTreeMap::from([(
+                "version".to_string(),
+                "{{ version }}".to_string(),
+            )])),
+        });
+
+        let result = render_deployment(&source, "canary", Some(1), 1, &Default::default());
+        assert!(matches!(result, Err(Error::ValidationError(_))));
+    }
+
+    #[test]
+    fn template_variables_are_opt_in() {
+        let mut source: MultiDeployment = serde_yaml::from_str(include_str!(
+            "../testdata/template-variables/multideployment.yaml"
+        ))
+        .unwrap();
+        source.spec.template_variables = None;
+        source
+            .spec
+            .root_template
+            .template
+            .spec
+            .as_mut()
+            .unwrap()
+            .containers[0]
+            .args = Some(vec!["--fmt={{.Name}}".to_string()]);
+
+        let deployment =
+            render_deployment(&source, "canary", Some(1), 1, &Default::default()).unwrap();
+        let pod_template = deployment.spec.unwrap().template;
+        assert_eq!(
+            pod_template.metadata.unwrap().labels.unwrap()["variant"],
+            "{{ child }}"
+        );
+        let container = &pod_template.spec.unwrap().containers[0];
+        assert_eq!(container.args, Some(vec!["--fmt={{.Name}}".to_string()]));
+    }
+
+    #[test]
+    fn placement_profiles_golden() {
+        assert_rendered(
+            include_str!("../testdata/placement-profiles/multideployment.yaml"),
+            include_str!("../testdata/placement-profiles/deployments.yaml"),
+            &[("on-demand", 1), ("spot", 3)],
+        );
+    }
+
+    #[test]
+    fn pod_labels_keep_selector() {
+        let mut source: MultiDeployment = serde_yaml::from_str(include_str!(
+            "../testdata/child-overrides/multideployment.yaml"
+        ))
+        .unwrap();
+        let child = source.spec.children.get_mut("batch").unwrap();
+        child.pod_metadata = Some(ChildMetadata {
+            labels: Some(BTreeMap::from([("app".to_string(), "batch".to_string())])),
+            annotations: None,
+        });
+
+        let result = render_deployment(&source, "batch", Some(1), 1, &Default::default());
+        assert!(matches!(result, Err(Error::ValidationError(_))));
+    }
+
+    #[test]
+    fn json_patch_failure() {
+        let mut source: MultiDeployment =
+            serde_yaml::from_str(include_str!("../testdata/json-patch/multideployment.yaml"))
+                .unwrap();
+        let child = source.spec.children.get_mut("debug").unwrap();
+        child.patches = Some(vec![JsonPatchOperation {
+            op: JsonPatchOp::Remove,
+            path: "/spec/template/spec/containers/5".to_string(),
+            value: None,
+            from: None,
+        }]);
+
+        let result = render_deployment(&source, "debug", Some(1), 1, &Default::default());
+        assert!(matches!(result, Err(Error::PatchError(_))));
+    }
+
+    #[test]
+    fn restarts_targeted_children() {
+        let mut source = strategic_merge_fixture();
+        source.spec.restarted_at = Some(serde_json::from_str("\"2025-01-01T00:00:00Z\"").unwrap());
+        let child = source.spec.children.get_mut("canary").unwrap();
+        child.restarted_at = Some(serde_json::from_str("\"2025-02-01T00:00:00Z\"").unwrap());
+        let child = source.spec.children.get_mut("spot").unwrap();
+        child.restarted_at = Some(serde_json::from_str("\"2024-12-01T00:00:00Z\"").unwrap());
+
+        let restarted_at = |child_name: &str| {
+            let deployment =
+                render_deployment(&source, child_name, Some(1), 1, &Default::default()).unwrap();
+            deployment
+                .spec
+                .unwrap()
+                .template
+                .metadata
+                .unwrap()
+                .annotations
+                .unwrap()[RESTARTED_AT_ANNOTATION]
+                .clone()
+        };
+        assert_eq!(restarted_at("canary"), "2025-02-01T00:00:00Z");
+        assert_eq!(restarted_at("spot"), "2025-01-01T00:00:00Z");
+        assert_eq!(restarted_at("stable"), "2025-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn rollout_order_and_template_hash() {
+        let mut source = strategic_merge_fixture();
+        let hash = |source: &MultiDeployment, child_name: &str| {
+            render_deployment(source, child_name, Some(1), 1, &Default::default())
+                .unwrap()
+                .annotations()
+                .get(TEMPLATE_HASH_ANNOTATION)
+                .cloned()
+        };
+        assert_eq!(hash(&source, "stable"), None);
+
+        source.spec.rollout = Some(serde_yaml::from_str("{order: [stable]}").unwrap());
+        assert_eq!(
+            rollout_order(&source).unwrap(),
+            ["stable", "canary", "spot"].map(String::from)
+        );
+        let before = hash(&source, "stable").unwrap();
+        // independent of the replicas, changed by the template
+        assert_eq!(
+            render_deployment(&source, "stable", Some(5), 1, &Default::default())
+                .unwrap()
+                .annotations()[TEMPLATE_HASH_ANNOTATION],
+            before
+        );
+        source.spec.restarted_at = Some(serde_json::from_str("\"2025-01-01T00:00:00Z\"").unwrap());
+        assert_ne!(hash(&source, "stable").unwrap(), before);
+
+        for order in ["{order: [stable, missing]}", "{order: [spot, spot]}"] {
+            source.spec.rollout = Some(serde_yaml::from_str(order).unwrap());
+            assert!(matches!(
+                allocate(&source, &Default::default()),
+                Err(Error::ValidationError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn orders_dependencies() {
+        let mut source = strategic_merge_fixture();
+        let depend = |source: &mut MultiDeployment, child_name: &str, dependencies: &[&str]| {
+            source.spec.children.get_mut(child_name).unwrap().depends_on =
+                Some(dependencies.iter().map(|d| d.to_string()).collect());
+        };
+        depend(&mut source, "canary", &["spot"]);
+        depend(&mut source, "spot", &["stable"]);
+        assert_eq!(
+            dependency_order(&source).unwrap(),
+            ["stable", "spot", "canary"].map(String::from)
+        );
+
+        for dependencies in [&["canary"][..], &["stable"], &["missing"]] {
+            depend(&mut source, "stable", dependencies);
+            assert!(matches!(
+                allocate(&source, &Default::default()),
+                Err(Error::ValidationError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_expiry() {
+        let mut source = strategic_merge_fixture();
+        let child = source.spec.children.get_mut("canary").unwrap();
+        child.ttl = Some("72h".to_string());
+        assert!(allocate(&source, &Default::default()).is_ok());
+
+        for (expires_at, ttl) in [(None, "3 days"), (Some("2025-01-01T00:00:00Z"), "72h")] {
+            let child = source.spec.children.get_mut("canary").unwrap();
+            child.ttl = Some(ttl.to_string());
+            child.expires_at = expires_at.map(|t| serde_json::from_value(t.into()).unwrap());
+            assert!(matches!(
+                allocate(&source, &Default::default()),
+                Err(Error::ValidationError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn scale_priorities_follow_input_changes() {
+        let mut source = strategic_merge_fixture();
+        source.spec.replicas = Some(7);
+        source
+            .spec
+            .children
+            .get_mut("spot")
+            .unwrap()
+            .scale_down_priority = Some(1);
+        let fresh = allocate(&source, &Default::default()).unwrap();
+
+        // targets reached by scale priorities are kept while the total is unchanged
+        let previous = |hash: &str| RenderOptions {
+            previous: Some(MultiDeploymentStatus {
+                children: Some(
+                    [("canary", 2), ("spot", 2), ("stable", 3)]
+                        .into_iter()
+                        .map(|(child_name, target_replicas)| {
+                            let child = crate::crd::ChildStatus {
+                                target_replicas,
+                                ..Default::default()
+                            };
+                            (child_name.to_string(), child)
+                        })
+                        .collect(),
+                ),
+                allocation_hash: Some(hash.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let kept = allocate(&source, &previous(&fresh.inputs_hash)).unwrap();
+        assert_eq!(kept.replicas, [2, 2, 3]);
+        assert_ne!(fresh.replicas, kept.replicas);
+
+        // other inputs than the weights re-split as well
+        for change in [
+            |s: &mut MultiDeployment| {
+                s.spec.allocation_strategy = Some(AllocationStrategy::FixedThenWeighted)
+            },
+            |s: &mut MultiDeployment| s.spec.rounding = Some(RoundingMode::Stable),
+            |s: &mut MultiDeployment| {
+                s.spec.children.get_mut("stable").unwrap().max_replicas = Some(10)
+            },
+        ] {
+            let mut source = source.clone();
+            change(&mut source);
+            let changed = allocate(&source, &previous(&fresh.inputs_hash)).unwrap();
+            assert_ne!(changed.inputs_hash, fresh.inputs_hash);
+            assert_eq!(changed.replicas, fresh.replicas);
+        }
+    }
+
+    #[test]
+    fn expires_children() {
+        let mut source = strategic_merge_fixture();
+        let time = |t: &str| DateTime::parse_from_rfc3339(t).unwrap().to_utc();
+        source.spec.children.get_mut("canary").unwrap().expires_at =
+            Some(Time(time("2025-01-01T00:00:00Z")));
+        source.spec.children.get_mut("spot").unwrap().ttl = Some("24h".to_string());
+        let mut options = RenderOptions {
+            now: Some(time("2025-01-01T12:00:00Z")),
+            deployments_created: BTreeMap::from([(
+                "spot".to_string(),
+                time("2024-12-31T18:00:00Z"),
+            )]),
+            ..Default::default()
+        };
+
+        let rendered = render(&source, &options).unwrap();
+        assert_eq!(rendered.expired_children, ["canary"].map(String::from));
+        assert_eq!(rendered.allocation.replicas, [0, 2, 2]);
+        let names: Vec<String> = rendered.deployments.iter().map(|d| d.name_any()).collect();
+        assert_eq!(names, ["web-spot", "web-stable"].map(String::from));
+
+        // the ttl counts from the creation of the Deployment
+        options.now = Some(time("2025-01-01T18:00:00Z"));
+        let expiries = expire(&mut source.clone(), &options);
+        assert_eq!(
+            expiries["spot"],
+            Expiry {
+                expires_at: time("2025-01-01T18:00:00Z"),
+                expired: true
+            }
+        );
+
+        // nothing expires without the current time
+        let rendered = render(&source, &Default::default()).unwrap();
+        assert!(rendered.expired_children.is_empty());
+        assert_eq!(rendered.deployments.len(), 3);
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TemplateError {
+    #[error("unknown template variable {0}")]
+    UnknownVariable(String),
+    #[error("unterminated template expression in {0:?}")]
+    Unterminated(String),
+}
+
+/// Replaces `{{ name }}` in `text` with the value of the variable `name`.
+/// `{{ "..." }}` is replaced with the quoted text, e.g. `{{ "{{" }}` for
+/// literal braces.
+pub fn render_str(text: &str, variables: &BTreeMap<&str, String>) -> Result<String, TemplateError> {
+    substitute(text, variables, true)
+}
+
+/// Like [`render_str`], but expressions of unknown variables and quoted text
+/// are kept, so that they can be rendered later with more variables.
+pub fn render_partial_str(
+    text: &str,
+    variables: &BTreeMap<&str, String>,
+) -> Result<String, TemplateError> {
+    substitute(text, variables, false)
+}
+
+fn substitute(
+    text: &str,
+    variables: &BTreeMap<&str, String>,
+    strict: bool,
+) -> Result<String, TemplateError> {
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let expression = &rest[start + 2..];
+        let end = closing_braces(expression)
+            .ok_or_else(|| TemplateError::Unterminated(text.to_string()))?;
+        let name = expression[..end].trim();
+        let literal = name.strip_prefix('"').and_then(|n| n.strip_suffix('"'));
+        match (literal, variables.get(name)) {
+            (Some(literal), _) if strict => rendered.push_str(literal),
+            (None, Some(value)) => rendered.push_str(value),
+            (None, None) if strict => {
+                return Err(TemplateError::UnknownVariable(name.to_string()));
+            }
+            _ => rendered.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &expression[end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Position of the `}}` closing an expression, skipping quoted text.
+fn closing_braces(expression: &str) -> Option<usize> {
+    let trimmed = expression.trim_start();
+    let offset = expression.len() - trimmed.len();
+    if let Some(quoted) = trimmed.strip_prefix('"') {
+        let quote_end = quoted.find('"')?;
+        let after = offset + 1 + quote_end + 1;
+        return expression[after..].find("}}").map(|end| after + end);
+    }
+    expression.find("}}")
+}
+
+/// Renders every string in `value`. Keys of objects are left as they are.
+pub fn render(value: &mut Value, variables: &BTreeMap<&str, String>) -> Result<(), TemplateError> {
+    render_strings(value, &|text| render_str(text, variables))
+}
+
+/// Renders every string in `value` with [`render_partial_str`].
+pub fn render_partial(
+    value: &mut Value,
+    variables: &BTreeMap<&str, String>,
+) -> Result<(), TemplateError> {
+    render_strings(value, &|text| render_partial_str(text, variables))
+}
+
+fn render_strings(
+    value: &mut Value,
+    render: &dyn Fn(&str) -> Result<String, TemplateError>,
+) -> Result<(), TemplateError> {
+    match value {
+        Value::String(text) if text.contains("{{") => *text = render(text)?,
+        Value::Array(items) => {
+            for item in items {
+                render_strings(item, render)?;
+            }
+        }
+        Value::Object(fields) => {
+            for field in fields.values_mut() {
+                render_strings(field, render)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn variables() -> BTreeMap<&'static str, String> {
+        BTreeMap::from([
+            ("child", "canary".to_string()),
+            ("replicas", "3".to_string()),
+        ])
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let vars = variables();
+        assert_eq!(
+            render_str("{{child}}-{{ replicas }}", &vars).unwrap(),
+            "canary-3"
+        );
+        assert_eq!(render_str("plain text", &vars).unwrap(), "plain text");
+        assert_eq!(render_str(r#"{{ "{{" }}x}}"#, &vars).unwrap(), "{{x}}");
+        assert_eq!(
+            render_str("{{ version }}", &vars),
+            Err(TemplateError::UnknownVariable("version".to_string()))
+        );
+        assert!(matches!(
+            render_str("{{ child", &vars),
+            Err(TemplateError::Unterminated(_))
+        ));
+    }
+
+    #[test]
+    fn partial_keeps_unknown_variables() {
+        let vars = variables();
+        assert_eq!(
+            render_partial_str(r#"{{ child }}/{{parent}}/{{ "{{" }}"#, &vars).unwrap(),
+            r#"canary/{{parent}}/{{ "{{" }}"#
+        );
+    }
+
+    #[test]
+    fn renders_nested_strings() {
+        let mut value = json!({
+            "env": [{"name": "VARIANT", "value": "{{ child }}"}],
+            "labels": {"{{ child }}": "x"},
+            "replicas": 1,
+        });
+        render(&mut value, &variables()).unwrap();
+
+        assert_eq!(
+            value,
+            json!({
+                "env": [{"name": "VARIANT", "value": "canary"}],
+                "labels": {"{{ child }}": "x"},
+                "replicas": 1,
+            })
+        );
+    }
+}
@@ -1,13 +1,24 @@
-use k8s_openapi::api::apps::v1::Deployment;
-use kube::Api;
+use k8s_openapi::api::{
+    apps::v1::Deployment,
+    core::v1::{Node, Pod},
+};
+use kube::{
+    Api,
+    runtime::{events::Recorder, reflector::Store},
+};
 use thiserror::Error;
 
-use crate::crd::MultiDeployment;
+use crate::crd::{MultiDeployment, PlacementProfile};
 use crate::utils::AllocationError;
 
 pub struct Context {
     pub multi_deployments: Api<MultiDeployment>,
     pub deployments: Api<Deployment>,
+    pub pods: Api<Pod>,
+    pub placement_profiles: Api<PlacementProfile>,
+    /// Cache of the nodes, only set when the controller watches nodes
+    pub nodes: Option<Store<Node>>,
+    pub recorder: Recorder,
 }
 
 #[derive(Error, Debug)]
@@ -20,6 +31,8 @@ pub enum Error {
     ParseError(#[from] kube::core::ParseExpressionError),
     #[error("Validation error: {0}")]
     ValidationError(String),
+    #[error("Patch error: {0}")]
+    PatchError(String),
     #[error("Replica calcuataion error: {0}")]
     ReplicaCalculationError(#[from] AllocationError),
 }
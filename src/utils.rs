@@ -2,6 +2,12 @@ use std::cmp::Ordering;
 use std::error::Error;
 use std::fmt;
 
+use k8s_openapi::{
+    api::{apps::v1::Deployment, core::v1::PodSpec},
+    apimachinery::pkg::{api::resource::Quantity, util::intstr::IntOrString},
+    chrono::TimeDelta,
+};
+
 #[derive(Debug)]
 pub enum AllocationError {
     LengthMismatch,
@@ -9,7 +15,10 @@ pub enum AllocationError {
     NegativeMinima,
     NaNOrNegativeWeight,
     Infeasible { required: i64, available: i64 },
+    InfeasibleUpper { capacity: i64, required: i64 },
+    MaximaBelowMinima,
     DownAdjustImpossible,
+    InvalidPercentage(String),
 }
 
 const EPSILON: f64 = 1e-12;
@@ -27,25 +36,29 @@ impl fmt::Display for AllocationError {
                 required,
                 available,
             } => write!(f, "infeasible: sum(m) = {} > N = {}", required, available),
+            AllocationError::InfeasibleUpper { capacity, required } => {
+                write!(f, "infeasible: sum(M) = {} < N = {}", capacity, required)
+            }
+            AllocationError::MaximaBelowMinima => write!(f, "all M_k must be at least m_k"),
             AllocationError::DownAdjustImpossible => {
                 write!(f, "could not decrease to meet N without violating minima")
             }
+            AllocationError::InvalidPercentage(value) => {
+                write!(f, "invalid percentage value: {}", value)
+            }
         }
     }
 }
 impl Error for AllocationError {}
 
-/// Water-filling continuous solution:
-///   x*_k = max(m_k, α * w_k) for some α s.t. sum x* = N
-fn water_filling_continuous(
+/// Checks shared by all strategies: N, m_k are non-negative, m_k <= M_k and
+/// sum(m) <= N <= sum(M)
+fn check_bounds(
     total: i64,
     minimums: &[i64],
-    weights: &[f64],
-) -> Result<Vec<f64>, AllocationError> {
-    let count = minimums.len() as usize;
-
-    // Basic checks
-    if minimums.len() != weights.len() {
+    maximums: &[Option<i64>],
+) -> Result<(), AllocationError> {
+    if minimums.len() != maximums.len() {
         return Err(AllocationError::LengthMismatch);
     }
     if total < 0 {
@@ -54,8 +67,8 @@ fn water_filling_continuous(
     if minimums.iter().any(|&mi| mi < 0) {
         return Err(AllocationError::NegativeMinima);
     }
-    if weights.iter().any(|&wi| !wi.is_finite() || wi < 0.0) {
-        return Err(AllocationError::NaNOrNegativeWeight);
+    if (0..minimums.len()).any(|i| maximums[i].is_some_and(|ma| ma < minimums[i])) {
+        return Err(AllocationError::MaximaBelowMinima);
     }
 
     let required: i64 = minimums.iter().sum();
@@ -65,6 +78,37 @@ fn water_filling_continuous(
             available: total,
         });
     }
+    if let Some(capacity) = maximums.iter().copied().sum::<Option<i64>>()
+        && capacity < total
+    {
+        return Err(AllocationError::InfeasibleUpper {
+            capacity,
+            required: total,
+        });
+    }
+    Ok(())
+}
+
+/// Water-filling continuous solution:
+///   x*_k = clamp(α * w_k, m_k, M_k) for some α s.t. sum x* = N
+/// If every weighted child is capped at its maximum, the rest overflows
+/// evenly to children that still have room.
+fn water_filling_continuous(
+    total: i64,
+    minimums: &[i64],
+    maximums: &[Option<i64>],
+    weights: &[f64],
+) -> Result<Vec<f64>, AllocationError> {
+    let count = minimums.len();
+
+    // Basic checks
+    if minimums.len() != weights.len() || maximums.len() != weights.len() {
+        return Err(AllocationError::LengthMismatch);
+    }
+    if weights.iter().any(|&wi| !wi.is_finite() || wi < 0.0) {
+        return Err(AllocationError::NaNOrNegativeWeight);
+    }
+    check_bounds(total, minimums, maximums)?;
 
     // If all weights is zero, assume as same weights
     let all_zero_weight = weights.iter().all(|&wi| wi.abs() <= EPSILON);
@@ -73,9 +117,11 @@ fn water_filling_continuous(
     } else {
         weights.to_vec()
     };
+    let upper = |i: usize| maximums[i].map_or(f64::INFINITY, |ma| ma as f64);
 
-    // clamped[i] == true means x_i = m_i
+    // clamped[i] == true means x_i sits on a bound: M_i if upper_clamped[i], else m_i
     let mut clamped = vec![false; count];
+    let mut upper_clamped = vec![false; count];
     let mut sum_m_l: f64 = 0.0;
     let mut sum_w_f: f64 = 0.0;
 
@@ -89,39 +135,70 @@ fn water_filling_continuous(
         }
     }
 
-    // Iteratively clamp violators where α w_i < m_i
+    // Iteratively clamp violators where α w_i < m_i or α w_i > M_i.
+    // Only the side with the larger total violation is clamped in one pass.
     loop {
         if sum_w_f <= EPSILON {
             break;
         }
         let alpha = ((total as f64) - sum_m_l) / sum_w_f;
 
-        let mut any = false;
+        let mut lower_violators = Vec::new();
+        let mut upper_violators = Vec::new();
+        let mut lower_violation = 0.0;
+        let mut upper_violation = 0.0;
         for i in 0..count {
-            if !clamped[i] && alpha * weights[i] < (minimums[i] as f64) - EPSILON {
+            if clamped[i] {
+                continue;
+            }
+            if alpha * weights[i] < (minimums[i] as f64) - EPSILON {
+                lower_violators.push(i);
+                lower_violation += (minimums[i] as f64) - alpha * weights[i];
+            } else if alpha * weights[i] > upper(i) + EPSILON {
+                upper_violators.push(i);
+                upper_violation += alpha * weights[i] - upper(i);
+            }
+        }
+        if lower_violators.is_empty() && upper_violators.is_empty() {
+            break;
+        }
+
+        if lower_violation >= upper_violation {
+            for &i in &lower_violators {
                 clamped[i] = true;
                 sum_m_l += minimums[i] as f64;
                 sum_w_f -= weights[i];
-                any = true;
             }
         }
-        if !any {
-            break;
+        if upper_violation >= lower_violation {
+            for &i in &upper_violators {
+                clamped[i] = true;
+                upper_clamped[i] = true;
+                sum_m_l += upper(i);
+                sum_w_f -= weights[i];
+            }
         }
     }
 
     // Build continuous solution
+    let bound = |i: usize| {
+        if upper_clamped[i] {
+            upper(i)
+        } else {
+            minimums[i] as f64
+        }
+    };
     let mut x_star = vec![0.0f64; count];
     if sum_w_f <= EPSILON {
-        // No free weights remain (or all zero): use minima
-        for i in 0..count {
-            x_star[i] = minimums[i] as f64;
+        // No free weights remain (or all zero): use bounds
+        for (i, xi) in x_star.iter_mut().enumerate() {
+            *xi = bound(i);
         }
     } else {
         let alpha = ((total as f64) - sum_m_l) / sum_w_f;
         for i in 0..count {
             if clamped[i] {
-                x_star[i] = minimums[i] as f64;
+                x_star[i] = bound(i);
             } else {
                 x_star[i] = alpha * weights[i];
             }
@@ -140,6 +217,25 @@ fn water_filling_continuous(
                     x_star[i] += add;
                 }
             }
+        } else if maximums.iter().any(|ma| ma.is_some()) {
+            // Weighted children are all capped: overflow the (integral) rest
+            // evenly to children that still have room
+            let headroom: Vec<Option<i64>> = (0..count)
+                .map(|i| maximums[i].map(|ma| ma - x_star[i].round() as i64))
+                .collect();
+            let overflow_weights: Vec<f64> = headroom
+                .iter()
+                .map(|h| if h.is_none_or(|h| h > 0) { 1.0 } else { 0.0 })
+                .collect();
+            let overflow = water_filling_continuous(
+                delta.round() as i64,
+                &vec![0; count],
+                &headroom,
+                &overflow_weights,
+            )?;
+            for i in 0..count {
+                x_star[i] += overflow[i];
+            }
         } else {
             // No free set; spread across all
             let add = delta / (count as f64);
@@ -152,22 +248,25 @@ fn water_filling_continuous(
     Ok(x_star)
 }
 
-/// Integer rounding to sum N with lower bounds:
-/// - base = max(m_i, floor(x*_i + 1e-12))
+/// Integer rounding to sum N with lower and upper bounds:
+/// - base = clamp(floor(x*_i + 1e-12), m_i, M_i)
 /// - give remaining units to largest fractional parts (ties broken deterministically)
-fn round_to_sum_with_lowers(
+fn round_to_sum_with_bounds(
     total: i64,
     x_star: &[f64],
     minimums: &[i64],
+    maximums: &[Option<i64>],
     weights: &[f64],
 ) -> Result<Vec<i64>, AllocationError> {
     let n = x_star.len();
     let eps_round = 1e-12;
+    let below_max = |i: usize, xi: i64| maximums[i].is_none_or(|ma| xi < ma);
 
-    // Floor, respect minima
+    // Floor, respect bounds
     let mut base: Vec<i64> = Vec::with_capacity(n);
     for i in 0..n {
         let flo = (x_star[i] + eps_round).floor() as i64;
+        let flo = maximums[i].map_or(flo, |ma| flo.min(ma));
         base.push(std::cmp::max(minimums[i], flo));
     }
     let bsum: i64 = base.iter().sum();
@@ -212,16 +311,28 @@ fn round_to_sum_with_lowers(
         let mut k = 0;
         while r > 0 && k < n {
             let i = order[k];
-            x[i] += 1;
-            r -= 1;
+            if below_max(i, x[i]) {
+                x[i] += 1;
+                r -= 1;
+            }
             k += 1;
         }
         // If still remaining (all fracs 0 and n < r), cycle deterministically
-        let mut i = 0usize;
         while r > 0 {
-            x[i % n] += 1;
-            r -= 1;
-            i += 1;
+            let mut progressed = false;
+            for (i, xi) in x.iter_mut().enumerate() {
+                if r > 0 && below_max(i, *xi) {
+                    *xi += 1;
+                    r -= 1;
+                    progressed = true;
+                }
+            }
+            if !progressed {
+                return Err(AllocationError::InfeasibleUpper {
+                    capacity: total - r,
+                    required: total,
+                });
+            }
         }
         Ok(x)
     } else {
@@ -263,16 +374,545 @@ fn round_to_sum_with_lowers(
     }
 }
 
+/// Integer rounding to sum N with bounds that moves as few replicas as
+/// possible away from `previous`: every child gets floor(x*_i) or
+/// floor(x*_i) + 1, and the +1s go to children that had more before,
+/// then to the largest fractional parts.
+fn round_stable(
+    total: i64,
+    x_star: &[f64],
+    minimums: &[i64],
+    maximums: &[Option<i64>],
+    weights: &[f64],
+    previous: &[i64],
+) -> Result<Vec<i64>, AllocationError> {
+    let n = x_star.len();
+    if previous.len() != n {
+        return Err(AllocationError::LengthMismatch);
+    }
+    let eps_round = 1e-12;
+
+    let mut x: Vec<i64> = Vec::with_capacity(n);
+    for i in 0..n {
+        let flo = (x_star[i] + eps_round).floor() as i64;
+        let flo = maximums[i].map_or(flo, |ma| flo.min(ma));
+        x.push(std::cmp::max(minimums[i], flo));
+    }
+    let r = total - x.iter().sum::<i64>();
+    if r < 0 {
+        // minima pushed the floor above N, nothing to choose
+        return round_to_sum_with_bounds(total, x_star, minimums, maximums, weights);
+    }
+
+    let frac: Vec<f64> = (0..n).map(|i| (x_star[i] - x[i] as f64).max(0.0)).collect();
+    let mut order: Vec<usize> = (0..n)
+        .filter(|&i| maximums[i].is_none_or(|ma| x[i] < ma))
+        .collect();
+    order.sort_by(|&i, &j| {
+        // Children that had more before first, then descending by frac,
+        // then descending by w, then ascending by index
+        let had_more_i = previous[i] > x[i];
+        let had_more_j = previous[j] > x[j];
+        had_more_j
+            .cmp(&had_more_i)
+            .then(
+                frac[i]
+                    .partial_cmp(&frac[j])
+                    .unwrap_or(Ordering::Equal)
+                    .reverse(),
+            )
+            .then(
+                weights[i]
+                    .partial_cmp(&weights[j])
+                    .unwrap_or(Ordering::Equal)
+                    .reverse(),
+            )
+            .then(i.cmp(&j))
+    });
+    if (order.len() as i64) < r {
+        return round_to_sum_with_bounds(total, x_star, minimums, maximums, weights);
+    }
+    for &i in order.iter().take(r as usize) {
+        x[i] += 1;
+    }
+    Ok(x)
+}
+
 pub fn allocate_weighted_with_minima(
     total: i64,
     minimums: &[i64],
     weights: &[f64],
 ) -> Result<Vec<i64>, AllocationError> {
-    let x_star = water_filling_continuous(total, minimums, weights)?;
-    let x_int = round_to_sum_with_lowers(total, &x_star, minimums, weights)?;
+    allocate_weighted_with_bounds(total, minimums, &vec![None; minimums.len()], weights)
+}
+
+/// Same as [`allocate_weighted_with_minima`], but no child gets more than its
+/// maximum (`None` means unbounded).
+pub fn allocate_weighted_with_bounds(
+    total: i64,
+    minimums: &[i64],
+    maximums: &[Option<i64>],
+    weights: &[f64],
+) -> Result<Vec<i64>, AllocationError> {
+    let x_star = water_filling_continuous(total, minimums, maximums, weights)?;
+    let x_int = round_to_sum_with_bounds(total, &x_star, minimums, maximums, weights)?;
     Ok(x_int)
 }
 
+/// Same as [`allocate_weighted_with_bounds`], but rounds towards the
+/// `previous` allocation so that small changes of N move as few replicas
+/// between children as possible. Every child stays within ±1 of its ideal share.
+pub fn allocate_weighted_stable(
+    total: i64,
+    minimums: &[i64],
+    maximums: &[Option<i64>],
+    weights: &[f64],
+    previous: &[i64],
+) -> Result<Vec<i64>, AllocationError> {
+    let x_star = water_filling_continuous(total, minimums, maximums, weights)?;
+    let all_zero_weight = weights.iter().all(|&wi| wi.abs() <= EPSILON);
+    let weights = if all_zero_weight {
+        vec![1.0f64; weights.len()]
+    } else {
+        weights.to_vec()
+    };
+    round_stable(total, &x_star, minimums, maximums, &weights, previous)
+}
+
+/// Every child gets its minimum first, then the rest is split by weight
+/// (capped by the maxima). With a `previous` allocation, the rest is rounded
+/// like [`allocate_weighted_stable`].
+pub fn allocate_fixed_then_weighted(
+    total: i64,
+    minimums: &[i64],
+    maximums: &[Option<i64>],
+    weights: &[f64],
+    previous: Option<&[i64]>,
+) -> Result<Vec<i64>, AllocationError> {
+    if minimums.len() != weights.len() {
+        return Err(AllocationError::LengthMismatch);
+    }
+    check_bounds(total, minimums, maximums)?;
+
+    let required: i64 = minimums.iter().sum();
+    let headroom: Vec<Option<i64>> = (0..minimums.len())
+        .map(|i| maximums[i].map(|ma| ma - minimums[i]))
+        .collect();
+    let zeros = vec![0; minimums.len()];
+    let rest = match previous {
+        Some(previous) => {
+            if previous.len() != minimums.len() {
+                return Err(AllocationError::LengthMismatch);
+            }
+            let previous_rest: Vec<i64> = (0..minimums.len())
+                .map(|i| (previous[i] - minimums[i]).max(0))
+                .collect();
+            allocate_weighted_stable(total - required, &zeros, &headroom, weights, &previous_rest)?
+        }
+        None => allocate_weighted_with_bounds(total - required, &zeros, &headroom, weights)?,
+    };
+    Ok((0..minimums.len()).map(|i| minimums[i] + rest[i]).collect())
+}
+
+/// Every child gets its minimum first, then the rest fills children in
+/// descending priority (ties by smaller index) up to their maxima.
+pub fn allocate_priority_fill(
+    total: i64,
+    minimums: &[i64],
+    maximums: &[Option<i64>],
+    priorities: &[i64],
+) -> Result<Vec<i64>, AllocationError> {
+    if minimums.len() != priorities.len() {
+        return Err(AllocationError::LengthMismatch);
+    }
+    check_bounds(total, minimums, maximums)?;
+
+    let mut order: Vec<usize> = (0..minimums.len()).collect();
+    order.sort_by(|&i, &j| priorities[j].cmp(&priorities[i]).then(i.cmp(&j)));
+
+    let mut x = minimums.to_vec();
+    let mut rest = total - minimums.iter().sum::<i64>();
+    for i in order {
+        let add = maximums[i].map_or(rest, |ma| rest.min(ma - x[i]));
+        x[i] += add;
+        rest -= add;
+    }
+    Ok(x)
+}
+
+/// Moves the allocation from `previous` to a new `total`, handing the
+/// difference to children by priority instead of recomputing from scratch:
+/// - on scale up, children with higher `up_priorities` gain first, but never
+///   beyond their `weighted` allocation
+/// - on scale down, children with higher `down_priorities` lose first, down
+///   to their minimum
+///
+/// Children with the same priority share the difference proportionally.
+pub fn allocate_marginal(
+    total: i64,
+    previous: &[i64],
+    weighted: &[i64],
+    minimums: &[i64],
+    maximums: &[Option<i64>],
+    up_priorities: &[i64],
+    down_priorities: &[i64],
+) -> Result<Vec<i64>, AllocationError> {
+    let n = minimums.len();
+    if [
+        previous.len(),
+        weighted.len(),
+        up_priorities.len(),
+        down_priorities.len(),
+    ]
+    .iter()
+    .any(|&len| len != n)
+    {
+        return Err(AllocationError::LengthMismatch);
+    }
+    check_bounds(total, minimums, maximums)?;
+
+    let mut x: Vec<i64> = (0..n)
+        .map(|i| {
+            let xi = previous[i].max(minimums[i]);
+            maximums[i].map_or(xi, |ma| xi.min(ma))
+        })
+        .collect();
+    let mut delta = total - x.iter().sum::<i64>();
+
+    let (priorities, scale_up) = if delta >= 0 {
+        (up_priorities, true)
+    } else {
+        (down_priorities, false)
+    };
+    let mut levels: Vec<i64> = priorities.to_vec();
+    levels.sort_unstable_by(|a, b| b.cmp(a));
+    levels.dedup();
+
+    for level in levels {
+        if delta == 0 {
+            break;
+        }
+        let capacities: Vec<i64> = (0..n)
+            .map(|i| {
+                if priorities[i] != level {
+                    0
+                } else if scale_up {
+                    let room = maximums[i].map_or(i64::MAX, |ma| ma - x[i]);
+                    (weighted[i] - x[i]).clamp(0, room)
+                } else {
+                    x[i] - minimums[i]
+                }
+            })
+            .collect();
+        let amount = delta.abs().min(capacities.iter().sum());
+        let change = distribute(amount, &capacities)?;
+        for i in 0..n {
+            if scale_up {
+                x[i] += change[i];
+            } else {
+                x[i] -= change[i];
+            }
+        }
+        delta -= if scale_up { amount } else { -amount };
+    }
+
+    if delta != 0 {
+        return Err(AllocationError::DownAdjustImpossible);
+    }
+    Ok(x)
+}
+
+/// Resolves an absolute count or a percentage (e.g. "25%") against `total`,
+/// the same way Deployments resolve `maxSurge` / `maxUnavailable`.
+pub fn resolve_int_or_percent(
+    value: &IntOrString,
+    total: i64,
+    round_up: bool,
+) -> Result<i64, AllocationError> {
+    match value {
+        IntOrString::Int(v) => Ok((*v).into()),
+        IntOrString::String(s) => {
+            let percent: i64 = s
+                .strip_suffix('%')
+                .and_then(|p| p.parse().ok())
+                .ok_or_else(|| AllocationError::InvalidPercentage(s.clone()))?;
+            let scaled = percent * total;
+            if round_up {
+                Ok((scaled + 99).div_euclid(100))
+            } else {
+                Ok(scaled.div_euclid(100))
+            }
+        }
+    }
+}
+
+/// Splits `amount` across children proportionally to `capacities`,
+/// never giving a child more than its capacity.
+fn distribute(amount: i64, capacities: &[i64]) -> Result<Vec<i64>, AllocationError> {
+    let zeros = vec![0; capacities.len()];
+    let weights: Vec<f64> = capacities.iter().map(|&c| c as f64).collect();
+    allocate_weighted_with_minima(amount, &zeros, &weights)
+}
+
+/// Next allocation from `previous` towards `target`, moving at most
+/// `max_moved` replicas between children. A change of the total is applied
+/// right away; only replicas taken from one child and given to another count
+/// against the limit.
+pub fn rebalance_step(
+    previous: &[i64],
+    target: &[i64],
+    max_moved: i64,
+) -> Result<Vec<i64>, AllocationError> {
+    if previous.len() != target.len() {
+        return Err(AllocationError::LengthMismatch);
+    }
+
+    let gains: Vec<i64> = (0..target.len())
+        .map(|i| (target[i] - previous[i]).max(0))
+        .collect();
+    let losses: Vec<i64> = (0..target.len())
+        .map(|i| (previous[i] - target[i]).max(0))
+        .collect();
+    let gained: i64 = gains.iter().sum();
+    let lost: i64 = losses.iter().sum();
+
+    let moved = gained.min(lost).min(max_moved.max(0));
+    let (gain_amount, loss_amount) = if gained >= lost {
+        (gained - lost + moved, moved)
+    } else {
+        (moved, lost - gained + moved)
+    };
+
+    let add = distribute(gain_amount, &gains)?;
+    let sub = distribute(loss_amount, &losses)?;
+    Ok((0..target.len())
+        .map(|i| previous[i] + add[i] - sub[i])
+        .collect())
+}
+
+/// Replicas of a child to move to its fallback child:
+/// - while some pods are unschedulable, everything allocated beyond the
+///   schedulable pods
+/// - otherwise keep what was moved before, until it is time to `retry` the child
+pub fn fallback_replicas(
+    allocated: i64,
+    pods: i64,
+    unschedulable: i64,
+    previous: i64,
+    retry: bool,
+) -> i64 {
+    if unschedulable > 0 {
+        (allocated - (pods - unschedulable)).clamp(0, allocated)
+    } else if retry {
+        0
+    } else {
+        previous.clamp(0, allocated)
+    }
+}
+
+/// Parses a resource quantity (e.g. "500m", "2Gi", "1e3") into a number
+pub fn parse_quantity(quantity: &Quantity) -> Option<f64> {
+    let value = quantity.0.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '+' || c == '-'))
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+
+    let multiplier = match suffix {
+        "" => 1.0,
+        "n" => 1e-9,
+        "u" => 1e-6,
+        "m" => 1e-3,
+        "k" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        "P" => 1e15,
+        "E" => 1e18,
+        "Ki" => 1024f64,
+        "Mi" => 1024f64.powi(2),
+        "Gi" => 1024f64.powi(3),
+        "Ti" => 1024f64.powi(4),
+        "Pi" => 1024f64.powi(5),
+        "Ei" => 1024f64.powi(6),
+        _ => {
+            let exponent: i32 = suffix
+                .strip_prefix('e')
+                .or_else(|| suffix.strip_prefix('E'))?
+                .parse()
+                .ok()?;
+            10f64.powi(exponent)
+        }
+    };
+    Some(number * multiplier)
+}
+
+/// Effective per-pod request of `resource`: the sum over containers, or the
+/// largest init container if that is more. Limits stand in for missing requests.
+pub fn pod_resource_request(pod_spec: &PodSpec, resource: &str) -> Option<f64> {
+    let request = |container: &k8s_openapi::api::core::v1::Container| {
+        let resources = container.resources.as_ref()?;
+        resources
+            .requests
+            .as_ref()
+            .and_then(|r| r.get(resource))
+            .or_else(|| resources.limits.as_ref().and_then(|l| l.get(resource)))
+            .and_then(parse_quantity)
+    };
+
+    let containers: f64 = pod_spec.containers.iter().filter_map(request).sum();
+    let init_containers = pod_spec
+        .init_containers
+        .iter()
+        .flatten()
+        .filter_map(request)
+        .fold(0.0, f64::max);
+    let total = containers.max(init_containers);
+    (total > 0.0).then_some(total)
+}
+
+/// Turns weights of capacity into weights of replicas, given the capacity of
+/// one replica of each child. The result sums up to the same total weight.
+pub fn capacity_weights(weights: &[f64], per_replica: &[f64]) -> Result<Vec<f64>, AllocationError> {
+    if weights.len() != per_replica.len() {
+        return Err(AllocationError::LengthMismatch);
+    }
+    if per_replica.iter().any(|&r| !r.is_finite() || r <= 0.0) {
+        return Err(AllocationError::NaNOrNegativeWeight);
+    }
+    let scaled: Vec<f64> = (0..weights.len())
+        .map(|i| weights[i] / per_replica[i])
+        .collect();
+    let total_weight: f64 = weights.iter().sum();
+    let total_scaled: f64 = scaled.iter().sum();
+    if total_scaled <= EPSILON {
+        return Ok(scaled);
+    }
+    Ok(scaled
+        .iter()
+        .map(|w| w * total_weight / total_scaled)
+        .collect())
+}
+
+/// Next replica step from `current` towards `target` that keeps the overall
+/// available count at or above sum(target) - max_unavailable:
+/// - children gaining replicas are scaled up right away
+/// - children losing replicas drop their unavailable replicas right away, and
+///   only shed available ones as far as the available surplus covers, in
+///   index order
+pub fn surge_safe_step(
+    current: &[i64],
+    available: &[i64],
+    target: &[i64],
+    max_unavailable: i64,
+) -> Result<Vec<i64>, AllocationError> {
+    if current.len() != target.len() || available.len() != target.len() {
+        return Err(AllocationError::LengthMismatch);
+    }
+
+    let total_available: i64 = available.iter().sum();
+    let total_target: i64 = target.iter().sum();
+    let mut budget = (total_available - (total_target - max_unavailable)).max(0);
+
+    let mut step = Vec::with_capacity(target.len());
+    for i in 0..target.len() {
+        if target[i] >= current[i] {
+            step.push(target[i]);
+        } else {
+            // replicas that never became available don't count against the budget
+            let surplus = current[i] - target[i];
+            let unavailable = surplus.min(current[i] - available[i]).max(0);
+            let shed = (surplus - unavailable).min(budget);
+            budget -= shed;
+            step.push(current[i] - unavailable - shed);
+        }
+    }
+    Ok(step)
+}
+
+/// Dependencies each child waits for before it gets replicas. `order` lists
+/// children after their dependencies. A child that isn't `released` yet (has
+/// no replicas) waits for dependencies that wait themselves or have fewer than
+/// `required` replicas available. Released children never wait again, so a
+/// dependency losing availability later doesn't scale them down.
+pub fn dependency_waits(
+    order: &[usize],
+    depends_on: &[Vec<usize>],
+    required: &[i64],
+    available: &[i64],
+    released: &[bool],
+) -> Vec<Vec<usize>> {
+    let mut waits = vec![Vec::new(); depends_on.len()];
+    for &i in order {
+        if released[i] {
+            continue;
+        }
+        waits[i] = depends_on[i]
+            .iter()
+            .copied()
+            .filter(|&j| !waits[j].is_empty() || available[j] < required[j])
+            .collect();
+    }
+    waits
+}
+
+/// Parses durations like `90m`, `1h30m` or `7d`.
+pub fn parse_duration(value: &str) -> Option<TimeDelta> {
+    let mut total = TimeDelta::zero();
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let n: i64 = number.parse().ok()?;
+        number.clear();
+        let part = match c {
+            's' => TimeDelta::try_seconds(n)?,
+            'm' => TimeDelta::try_minutes(n)?,
+            'h' => TimeDelta::try_hours(n)?,
+            'd' => TimeDelta::try_days(n)?,
+            _ => return None,
+        };
+        total = total.checked_add(&part)?;
+    }
+    (number.is_empty() && !value.is_empty()).then_some(total)
+}
+
+/// Whether a Deployment finished rolling out its pod template, like
+/// `kubectl rollout status` checks it.
+pub fn rollout_complete(deployment: &Deployment) -> bool {
+    let Some(status) = &deployment.status else {
+        return false;
+    };
+    let replicas = deployment
+        .spec
+        .as_ref()
+        .and_then(|s| s.replicas)
+        .unwrap_or(1);
+    let updated = status.updated_replicas.unwrap_or(0);
+    status.observed_generation >= deployment.metadata.generation
+        && updated >= replicas
+        && status.replicas.unwrap_or(0) <= updated
+        && status.available_replicas.unwrap_or(0) >= updated
+}
+
+/// Whether a Deployment stopped progressing within `progressDeadlineSeconds`.
+pub fn progress_deadline_exceeded(deployment: &Deployment) -> bool {
+    deployment
+        .status
+        .as_ref()
+        .and_then(|s| s.conditions.as_ref())
+        .is_some_and(|conditions| {
+            conditions.iter().any(|c| {
+                c.type_ == "Progressing"
+                    && c.status == "False"
+                    && c.reason.as_deref() == Some("ProgressDeadlineExceeded")
+            })
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -339,4 +979,541 @@ mod tests {
         // expected
         assert_eq!(result, vec![3, 3, 2, 2]);
     }
+
+    #[test]
+    fn int_or_percent() {
+        let pct = IntOrString::String("25%".to_string());
+        assert_eq!(resolve_int_or_percent(&pct, 10, false).unwrap(), 2);
+        assert_eq!(resolve_int_or_percent(&pct, 10, true).unwrap(), 3);
+        assert_eq!(
+            resolve_int_or_percent(&IntOrString::Int(4), 10, false).unwrap(),
+            4
+        );
+
+        let err =
+            resolve_int_or_percent(&IntOrString::String("25".to_string()), 10, false).unwrap_err();
+        match err {
+            AllocationError::InvalidPercentage(_) => {}
+            _ => panic!("expected invalid percentage"),
+        }
+    }
+
+    #[test]
+    fn surge_safe_scales_up_before_down() {
+        // move 5 replicas from child 0 to child 1, nothing available on child 1 yet
+        let step = surge_safe_step(&[10, 0], &[10, 0], &[5, 5], 0).unwrap();
+        assert_eq!(step, vec![10, 5]);
+
+        // 3 of the new replicas became available
+        let step = surge_safe_step(&[10, 5], &[10, 3], &[5, 5], 0).unwrap();
+        assert_eq!(step, vec![7, 5]);
+
+        // all available: finish the move
+        let step = surge_safe_step(&[7, 5], &[7, 5], &[5, 5], 0).unwrap();
+        assert_eq!(step, vec![5, 5]);
+
+        // max_unavailable allows shedding ahead of availability
+        let step = surge_safe_step(&[10, 0], &[10, 0], &[5, 5], 2).unwrap();
+        assert_eq!(step, vec![8, 5]);
+    }
+
+    #[test]
+    fn surge_safe_scale_down_is_immediate() {
+        // total shrinks: the surplus covers every reduction
+        let step = surge_safe_step(&[6, 4], &[6, 4], &[3, 2], 0).unwrap();
+        assert_eq!(step, vec![3, 2]);
+    }
+
+    #[test]
+    fn surge_safe_drops_unavailable_replicas() {
+        // a losing child whose pods never become available, e.g. a crashlooping
+        // canary set to weight 0
+        let step = surge_safe_step(&[5, 5], &[5, 0], &[10, 0], 0).unwrap();
+        assert_eq!(step, vec![10, 0]);
+
+        // available replicas of the losing child still wait for the surplus
+        let step = surge_safe_step(&[5, 5], &[5, 2], &[10, 0], 0).unwrap();
+        assert_eq!(step, vec![10, 2]);
+        let step = surge_safe_step(&[10, 2], &[10, 2], &[10, 0], 0).unwrap();
+        assert_eq!(step, vec![10, 0]);
+    }
+
+    #[test]
+    fn rebalance_limits_moves() {
+        // 90/10 -> 10/90 on 100 replicas, 20 at a time
+        let mut allocation = vec![90, 10];
+        let target = vec![10, 90];
+        let mut steps = 0;
+        while allocation != target {
+            let next = rebalance_step(&allocation, &target, 20).unwrap();
+            assert_eq!(next.iter().sum::<i64>(), 100);
+            assert!(allocation[0] - next[0] <= 20);
+            allocation = next;
+            steps += 1;
+        }
+        assert_eq!(steps, 4);
+    }
+
+    #[test]
+    fn rebalance_applies_total_change_immediately() {
+        // scale up by 10 while moving: the extra replicas are not limited
+        let next = rebalance_step(&[90, 10], &[20, 90], 5).unwrap();
+        assert_eq!(next, vec![85, 25]);
+
+        // scale down by 10 while moving
+        let next = rebalance_step(&[90, 10], &[0, 90], 5).unwrap();
+        assert_eq!(next, vec![75, 15]);
+
+        // nothing to move between children
+        let next = rebalance_step(&[5, 5], &[7, 8], 0).unwrap();
+        assert_eq!(next, vec![7, 8]);
+    }
+
+    #[test]
+    fn fixed_then_weighted() {
+        // minima are a fixed base, the rest is split 70/30
+        let result =
+            allocate_fixed_then_weighted(12, &[2, 0], &[None, None], &[70.0, 30.0], None).unwrap();
+        assert_eq!(result, vec![9, 3]);
+
+        // weighted would have raised child 1 to its minimum instead
+        let result =
+            allocate_weighted_with_bounds(12, &[0, 4], &[None, None], &[70.0, 30.0]).unwrap();
+        assert_eq!(result, vec![8, 4]);
+        let result =
+            allocate_fixed_then_weighted(12, &[0, 4], &[None, None], &[70.0, 30.0], None).unwrap();
+        assert_eq!(result, vec![6, 6]);
+
+        let result =
+            allocate_fixed_then_weighted(12, &[0, 4], &[None, Some(5)], &[70.0, 30.0], None)
+                .unwrap();
+        assert_eq!(result, vec![7, 5]);
+
+        match allocate_fixed_then_weighted(3, &[2, 2], &[None, None], &[1.0, 1.0], None) {
+            Err(AllocationError::Infeasible { .. }) => {}
+            other => panic!("expected infeasible, got {other:?}"),
+        }
+        match allocate_fixed_then_weighted(9, &[2, 2], &[Some(4), Some(4)], &[1.0, 1.0], None) {
+            Err(AllocationError::InfeasibleUpper { .. }) => {}
+            other => panic!("expected infeasible upper bound, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn priority_fill() {
+        // on-demand (priority 1) is filled up to 5, everything else goes to spot
+        let result = allocate_priority_fill(12, &[0, 1], &[Some(5), None], &[1, 0]).unwrap();
+        assert_eq!(result, vec![5, 7]);
+        let result = allocate_priority_fill(4, &[0, 1], &[Some(5), None], &[1, 0]).unwrap();
+        assert_eq!(result, vec![3, 1]);
+
+        // ties are filled in index order
+        let result =
+            allocate_priority_fill(6, &[0, 0, 0], &[Some(2), Some(2), None], &[0, 0, 0]).unwrap();
+        assert_eq!(result, vec![2, 2, 2]);
+
+        match allocate_priority_fill(3, &[2, 2], &[None, None], &[0, 0]) {
+            Err(AllocationError::Infeasible { .. }) => {}
+            other => panic!("expected infeasible, got {other:?}"),
+        }
+        match allocate_priority_fill(9, &[0, 0], &[Some(4), Some(4)], &[0, 0]) {
+            Err(AllocationError::InfeasibleUpper { .. }) => {}
+            other => panic!("expected infeasible upper bound, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn fallback() {
+        // 3 of 10 pods unschedulable: move them
+        assert_eq!(fallback_replicas(10, 10, 3, 0, false), 3);
+        // child still holds the pending pods while the fallback scales up
+        assert_eq!(fallback_replicas(10, 10, 3, 3, false), 3);
+        // child was scaled down, one more pod became unschedulable
+        assert_eq!(fallback_replicas(10, 7, 1, 3, false), 4);
+        // everything scheduled: keep until retry
+        assert_eq!(fallback_replicas(10, 6, 0, 4, false), 4);
+        assert_eq!(fallback_replicas(10, 6, 0, 4, true), 0);
+        // allocation shrank below what was moved
+        assert_eq!(fallback_replicas(2, 6, 0, 4, false), 2);
+    }
+
+    #[test]
+    fn fallback_settles() {
+        // 3 of 10 pods of child 0 cannot be scheduled, child 1 is its fallback.
+        // The pending pods are dropped right away, they were never available.
+        let moved = fallback_replicas(10, 10, 3, 0, false);
+        let effective = [10 - moved, moved];
+        assert_eq!(
+            surge_safe_step(&[10, 0], &[7, 0], &effective, 0).unwrap(),
+            effective
+        );
+
+        // the fallback pods became available: settled
+        let moved = fallback_replicas(10, 7, 0, moved, false);
+        let effective = [10 - moved, moved];
+        assert_eq!(
+            surge_safe_step(&[7, 3], &[7, 3], &effective, 0).unwrap(),
+            effective
+        );
+        assert_eq!(
+            surge_safe_step(&[10, 0], &[7, 3], &effective, 0).unwrap(),
+            effective
+        );
+
+        // retry: the child scales up first, the fallback keeps its pods
+        let moved = fallback_replicas(10, 7, 0, moved, true);
+        let effective = [10 - moved, moved];
+        assert_eq!(
+            surge_safe_step(&[7, 3], &[7, 3], &effective, 0).unwrap(),
+            vec![10, 3]
+        );
+
+        // still unschedulable: moved again
+        let moved = fallback_replicas(10, 10, 3, 0, false);
+        let retried = [10 - moved, moved];
+        assert_eq!(
+            surge_safe_step(&[10, 3], &[7, 3], &retried, 0).unwrap(),
+            retried
+        );
+
+        // or schedulable now: the fallback is scaled down once they are available
+        assert_eq!(
+            surge_safe_step(&[10, 3], &[10, 3], &effective, 0).unwrap(),
+            effective
+        );
+    }
+
+    #[test]
+    fn marginal_priorities() {
+        // spot (index 0) is shed first on scale down, down to its minimum
+        let result = allocate_marginal(
+            5,
+            &[7, 3],
+            &[4, 1],
+            &[0, 1],
+            &[None, None],
+            &[1, 0],
+            &[1, 0],
+        )
+        .unwrap();
+        assert_eq!(result, vec![2, 3]);
+
+        let result = allocate_marginal(
+            2,
+            &[7, 3],
+            &[1, 1],
+            &[0, 1],
+            &[None, None],
+            &[1, 0],
+            &[1, 0],
+        )
+        .unwrap();
+        assert_eq!(result, vec![0, 2]);
+
+        // spot gains first on scale up, on-demand only beyond spot's weighted share
+        let result = allocate_marginal(
+            10,
+            &[2, 3],
+            &[7, 3],
+            &[0, 1],
+            &[None, None],
+            &[1, 0],
+            &[1, 0],
+        )
+        .unwrap();
+        assert_eq!(result, vec![7, 3]);
+
+        let result = allocate_marginal(
+            10,
+            &[0, 2],
+            &[7, 3],
+            &[0, 1],
+            &[None, None],
+            &[1, 0],
+            &[1, 0],
+        )
+        .unwrap();
+        assert_eq!(result, vec![7, 3]);
+
+        // same priority shares the difference
+        let result = allocate_marginal(
+            8,
+            &[2, 2],
+            &[4, 4],
+            &[0, 0],
+            &[None, None],
+            &[0, 0],
+            &[0, 0],
+        )
+        .unwrap();
+        assert_eq!(result, vec![4, 4]);
+    }
+
+    #[test]
+    fn stable_avoids_reshuffling() {
+        // largest remainder takes the replica of child 0 away going from 3 to 4
+        let weights = [1.0, 3.0, 3.0];
+        let none = [None, None, None];
+        let zeros = [0, 0, 0];
+        assert_eq!(
+            allocate_weighted_with_bounds(3, &zeros, &none, &weights).unwrap(),
+            vec![1, 1, 1]
+        );
+        assert_eq!(
+            allocate_weighted_with_bounds(4, &zeros, &none, &weights).unwrap(),
+            vec![0, 2, 2]
+        );
+        assert_eq!(
+            allocate_weighted_stable(4, &zeros, &none, &weights, &[1, 1, 1]).unwrap(),
+            vec![1, 2, 1]
+        );
+
+        let weights = [1.0, 1.0, 1.0];
+        // same total: nothing moves
+        assert_eq!(
+            allocate_weighted_stable(10, &zeros, &none, &weights, &[3, 3, 4]).unwrap(),
+            vec![3, 3, 4]
+        );
+        // previous far from the ideal: stay within ±1
+        assert_eq!(
+            allocate_weighted_stable(10, &zeros, &none, &weights, &[10, 0, 0]).unwrap(),
+            vec![4, 3, 3]
+        );
+    }
+
+    #[test]
+    fn quantities() {
+        let parse = |s: &str| parse_quantity(&Quantity(s.to_string()));
+        assert_eq!(parse("500m"), Some(0.5));
+        assert_eq!(parse("2"), Some(2.0));
+        assert_eq!(parse("1.5"), Some(1.5));
+        assert_eq!(parse("2Gi"), Some(2.0 * 1024.0 * 1024.0 * 1024.0));
+        assert_eq!(parse("128M"), Some(128e6));
+        assert_eq!(parse("1e3"), Some(1000.0));
+        assert_eq!(parse("1E3"), Some(1000.0));
+        assert_eq!(parse("1E"), Some(1e18));
+        assert_eq!(parse("abc"), None);
+        assert_eq!(parse("1Xi"), None);
+    }
+
+    #[test]
+    fn capacity_normalized_weights() {
+        // 2-CPU and 4-CPU pods, 50/50 of CPU: twice as many small pods
+        let weights = capacity_weights(&[50.0, 50.0], &[2.0, 4.0]).unwrap();
+        let result = allocate_weighted_with_bounds(12, &[0, 0], &[None, None], &weights).unwrap();
+        assert_eq!(result, vec![8, 4]);
+
+        // memory sized requests don't get lost to the epsilon
+        let weights = capacity_weights(&[70.0, 30.0], &[4e9, 8e9]).unwrap();
+        assert!((weights.iter().sum::<f64>() - 100.0).abs() < 1e-9);
+        let result = allocate_weighted_with_bounds(17, &[0, 0], &[None, None], &weights).unwrap();
+        assert_eq!(result, vec![14, 3]);
+    }
+
+    /// Small deterministic xorshift generator for property tests
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> i64 {
+            (self.next() % n) as i64
+        }
+    }
+
+    #[test]
+    fn bounded_respects_bounds() {
+        let mut rng = Rng(0x5eed);
+        for _ in 0..2000 {
+            let n = 1 + rng.below(6) as usize;
+            let minimums: Vec<i64> = (0..n).map(|_| rng.below(5)).collect();
+            let maximums: Vec<Option<i64>> = minimums
+                .iter()
+                .map(|&m| (rng.below(3) > 0).then(|| m + rng.below(20)))
+                .collect();
+            let weights: Vec<f64> = (0..n).map(|_| rng.below(4) as f64 * 0.75).collect();
+
+            let required: i64 = minimums.iter().sum();
+            let capacity = maximums.iter().copied().sum::<Option<i64>>();
+            let upto = capacity.unwrap_or(required + 50);
+            let total = required + rng.below((upto - required + 1) as u64);
+
+            let result = allocate_weighted_with_bounds(total, &minimums, &maximums, &weights)
+                .unwrap_or_else(|e| panic!("{e}: {total} {minimums:?} {maximums:?} {weights:?}"));
+            assert_eq!(result.iter().sum::<i64>(), total);
+            for i in 0..n {
+                assert!(result[i] >= minimums[i]);
+                assert!(maximums[i].is_none_or(|ma| result[i] <= ma));
+            }
+
+            // no replica can move between weighted children to get closer to the weights
+            for i in 0..n {
+                for j in 0..n {
+                    if weights[i] == 0.0 || weights[j] == 0.0 {
+                        continue;
+                    }
+                    let i_has_room = maximums[i].is_none_or(|ma| result[i] < ma);
+                    if i_has_room && result[j] > minimums[j] {
+                        assert!(
+                            (result[j] - 1) as f64 / weights[j]
+                                <= (result[i] + 1) as f64 / weights[i] + 1e-9,
+                            "{total} {minimums:?} {maximums:?} {weights:?} => {result:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bounded_without_maxima_matches_minima() {
+        let mut rng = Rng(0xfeed);
+        for _ in 0..500 {
+            let n = 1 + rng.below(6) as usize;
+            let minimums: Vec<i64> = (0..n).map(|_| rng.below(5)).collect();
+            let weights: Vec<f64> = (0..n).map(|_| rng.below(10) as f64).collect();
+            let total = minimums.iter().sum::<i64>() + rng.below(40);
+
+            assert_eq!(
+                allocate_weighted_with_bounds(total, &minimums, &vec![None; n], &weights).unwrap(),
+                allocate_weighted_with_minima(total, &minimums, &weights).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn bounded_caps_and_overflows() {
+        // on-demand child never exceeds 5 pods
+        let result =
+            allocate_weighted_with_bounds(20, &[2, 0], &[Some(5), None], &[50.0, 50.0]).unwrap();
+        assert_eq!(result, vec![5, 15]);
+
+        // weighted children are capped, the rest overflows to the zero-weight child
+        let result =
+            allocate_weighted_with_bounds(10, &[0, 0], &[Some(4), None], &[1.0, 0.0]).unwrap();
+        assert_eq!(result, vec![4, 6]);
+
+        let err = allocate_weighted_with_bounds(10, &[0, 0], &[Some(4), Some(5)], &[1.0, 1.0])
+            .unwrap_err();
+        match err {
+            AllocationError::InfeasibleUpper {
+                capacity: 9,
+                required: 10,
+            } => {}
+            _ => panic!("expected infeasible upper bound"),
+        }
+
+        let err =
+            allocate_weighted_with_bounds(10, &[3, 0], &[Some(2), None], &[1.0, 1.0]).unwrap_err();
+        match err {
+            AllocationError::MaximaBelowMinima => {}
+            _ => panic!("expected maxima below minima"),
+        }
+    }
+
+    #[test]
+    fn stable_is_monotonic() {
+        let mut rng = Rng(0xabcd);
+        for _ in 0..300 {
+            let n = 1 + rng.below(6) as usize;
+            let minimums: Vec<i64> = (0..n).map(|_| rng.below(4)).collect();
+            let maximums = vec![None; n];
+            let weights: Vec<f64> = (0..n).map(|_| 1.0 + rng.below(7) as f64 * 0.5).collect();
+
+            let start = minimums.iter().sum::<i64>();
+            let mut previous =
+                allocate_weighted_with_bounds(start, &minimums, &maximums, &weights).unwrap();
+            for total in start + 1..start + 60 {
+                let result =
+                    allocate_weighted_stable(total, &minimums, &maximums, &weights, &previous)
+                        .unwrap();
+                assert_eq!(result.iter().sum::<i64>(), total);
+                for i in 0..n {
+                    assert!(
+                        result[i] >= previous[i],
+                        "{total} {minimums:?} {weights:?}: {previous:?} => {result:?}"
+                    );
+                }
+                previous = result;
+            }
+        }
+    }
+
+    #[test]
+    fn rollout_status() {
+        let deployment = |status: &str| -> Deployment {
+            serde_yaml::from_str(&format!(
+                "{{metadata: {{generation: 2}}, spec: {{replicas: 3, selector: {{}}, template: {{}}}}, status: {}}}",
+                status
+            ))
+            .unwrap()
+        };
+
+        let complete = deployment(
+            "{observedGeneration: 2, replicas: 3, updatedReplicas: 3, availableReplicas: 3}",
+        );
+        assert!(rollout_complete(&complete));
+        for status in [
+            // not observed yet
+            "{observedGeneration: 1, replicas: 3, updatedReplicas: 3, availableReplicas: 3}",
+            // old pods still running
+            "{observedGeneration: 2, replicas: 4, updatedReplicas: 3, availableReplicas: 3}",
+            // new pods not available
+            "{observedGeneration: 2, replicas: 3, updatedReplicas: 3, availableReplicas: 2}",
+        ] {
+            assert!(!rollout_complete(&deployment(status)), "{}", status);
+        }
+
+        let stalled = deployment(
+            "{observedGeneration: 2, replicas: 4, updatedReplicas: 1, conditions: [
+                {type: Progressing, status: 'False', reason: ProgressDeadlineExceeded}]}",
+        );
+        assert!(progress_deadline_exceeded(&stalled));
+        assert!(!progress_deadline_exceeded(&complete));
+    }
+
+    #[test]
+    fn dependencies() {
+        // 2 depends on 1, which depends on 0
+        let order = [0, 1, 2];
+        let depends_on = [vec![], vec![0], vec![1]];
+        let required = [2, 1, 1];
+
+        // nothing started: 1 waits for 0, 2 waits for 1 in turn
+        let waits = dependency_waits(&order, &depends_on, &required, &[1, 0, 0], &[false; 3]);
+        assert_eq!(waits, [vec![], vec![0], vec![1]]);
+        // 0 available: 1 starts, 2 still waits
+        let waits = dependency_waits(
+            &order,
+            &depends_on,
+            &required,
+            &[2, 0, 0],
+            &[true, false, false],
+        );
+        assert_eq!(waits, [vec![], vec![], vec![1]]);
+
+        // released children keep their replicas when a dependency drops, e.g.
+        // during its rolling update
+        let waits = dependency_waits(&order, &depends_on, &required, &[0, 0, 1], &[true; 3]);
+        assert_eq!(waits, [Vec::<usize>::new(), vec![], vec![]]);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90m"), Some(TimeDelta::minutes(90)));
+        assert_eq!(
+            parse_duration("1h30m"),
+            Some(TimeDelta::hours(1) + TimeDelta::minutes(30))
+        );
+        assert_eq!(parse_duration("7d"), Some(TimeDelta::days(7)));
+        assert_eq!(parse_duration("45s"), Some(TimeDelta::seconds(45)));
+        for invalid in ["", "10", "h", "1w", "1.5h", "-1h"] {
+            assert_eq!(parse_duration(invalid), None, "{}", invalid);
+        }
+    }
 }